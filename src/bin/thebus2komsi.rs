@@ -1,7 +1,7 @@
 use clap::Parser;
 use the_bus_2_komsi::opts::Opts;
 use the_bus_2_komsi::serial::{show_precise_com_ports};
-use the_bus_2_komsi::realmain::real_main;
+use the_bus_2_komsi::realmain::{check_config, real_main, run_console, run_test_pattern, setup};
 
 #[tokio::main]
 async fn main() {
@@ -12,6 +12,27 @@ async fn main() {
         return;
     }
 
+    if opts.setup {
+        setup(&opts).await;
+        return;
+    }
+
+    if opts.console {
+        run_console(&opts).await;
+        return;
+    }
+
+    if opts.test_pattern {
+        run_test_pattern(&opts).await;
+        return;
+    }
+
+    if opts.check_config {
+        // setup scripts rely on the exit code
+        let ok = check_config(&opts).await;
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
     // default
     real_main(&opts).await;
 }
@@ -0,0 +1,62 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use clap::Parser;
+use the_bus_2_komsi::mockapi::{scenario, MockApi, MockServer};
+use the_bus_2_komsi::opts::MockOpts;
+
+#[tokio::main]
+async fn main() {
+    let opts = MockOpts::parse();
+
+    let api = match MockApi::from_dir(&opts.fixtures) {
+        Ok(api) => api,
+        Err(e) => {
+            eprintln!("Error reading fixtures {}: {}", opts.fixtures.display(), e);
+            return;
+        }
+    };
+
+    let names = api.vehicle_names();
+    let Some(vehicle) = opts.vehicle.clone().or_else(|| names.first().cloned()) else {
+        eprintln!("No vehicles found in {}.", opts.fixtures.display());
+        return;
+    };
+
+    if opts.scenario {
+        // the scenario starts with the fixture of the vehicle
+        match api.vehicle(&vehicle) {
+            Some(fixture) => api.add_frames(scenario(&fixture)),
+            None => {
+                eprintln!("Vehicle {} not found in {}.", vehicle, opts.fixtures.display());
+                return;
+            }
+        }
+        api.requests_per_frame(opts.frame_requests);
+        api.looping(true);
+    }
+    api.enter(&vehicle);
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, opts.port));
+    let server = match MockServer::start(api.clone(), addr).await {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Error starting mock API on {}: {}", addr, e);
+            return;
+        }
+    };
+
+    println!(
+        "TheBusMockAPI {} is listening on {}, player sits in {}.",
+        env!("CARGO_PKG_VERSION"),
+        server.addr(),
+        vehicle
+    );
+    println!("Vehicles: {}", names.join(", "));
+
+    // the clock of the world runs in real time
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        api.advance_clock(Duration::from_secs(1));
+    }
+}
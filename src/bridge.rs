@@ -0,0 +1,901 @@
+// The bridge between TheBus and the dashboard hardware:
+// poll the telemetry source, map the vehicle to a KOMSI state, diff it and write the changes to all sinks.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use komsi::vehicle::{VehicleLogger, VehicleState};
+use komsi::{KomsiCommand, KomsiDateTime};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout};
+
+use the_bus_telemetry::api::ApiWorldType;
+use the_bus_telemetry::api2vehicle::get_vehicle_state_from_api;
+use the_bus_telemetry::ApiVehicleType;
+
+use crate::input::InputMap;
+use crate::mapping::{append_commands, compare_extra, ExtraValues, Mapping};
+use crate::opts::Opts;
+use crate::profile::Profiles;
+use crate::error::TelemetryError;
+use crate::schedule::{keep_slow_values, Backoff, Pacer, Timer};
+use crate::values::split_frame;
+use crate::sink::KomsiSink;
+use crate::source::{SourceError, TelemetrySource};
+
+struct PrintLogger;
+
+impl VehicleLogger for PrintLogger {
+    fn log(&self, msg: String) {
+        println!("{}", msg);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BridgeOptions {
+    /// Poll interval
+    pub interval: Duration,
+    /// Wait time after an error or if not in a bus, it doubles while no bus is found
+    pub error_interval: Duration,
+    /// Longest wait while no bus is found
+    pub max_error_interval: Duration,
+    /// How often slow values like fuel are taken
+    pub slow_interval: Duration,
+    /// How often the world (date and time) is read
+    pub world_interval: Duration,
+    /// How often the vehicle name is read again
+    pub vehicle_name_interval: Duration,
+    /// Sent when the player leaves the bus, TheBus closes and at exit, None: nothing is sent
+    pub safe_state: Option<Vec<u8>>,
+    /// How often the simulator type is sent to show the host is alive, zero: never
+    pub heartbeat_interval: Duration,
+    pub verbose: bool,
+    pub debug: bool,
+    pub debug_serial: bool,
+    pub debug_command: bool,
+}
+
+impl Default for BridgeOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(200),
+            error_interval: Duration::from_millis(1500),
+            max_error_interval: Duration::from_secs(10),
+            slow_interval: Duration::from_secs(1),
+            world_interval: Duration::from_secs(60),
+            vehicle_name_interval: Duration::from_secs(2),
+            safe_state: Some(safe_state_frame(DEFAULT_SAFE_STATE).unwrap()),
+            heartbeat_interval: Duration::ZERO,
+            verbose: false,
+            debug: false,
+            debug_serial: false,
+            debug_command: false,
+        }
+    }
+}
+
+impl BridgeOptions {
+    pub fn from_opts(opts: &Opts) -> Self {
+        Self {
+            verbose: opts.verbose,
+            debug: opts.debug,
+            debug_serial: opts.debug_serial,
+            debug_command: opts.debug_command,
+            ..Default::default()
+        }
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn error_interval(mut self, error_interval: Duration) -> Self {
+        self.error_interval = error_interval;
+        self
+    }
+
+    pub fn slow_interval(mut self, slow_interval: Duration) -> Self {
+        self.slow_interval = slow_interval;
+        self
+    }
+
+    pub fn world_interval(mut self, world_interval: Duration) -> Self {
+        self.world_interval = world_interval;
+        self
+    }
+
+    pub fn vehicle_name_interval(mut self, vehicle_name_interval: Duration) -> Self {
+        self.vehicle_name_interval = vehicle_name_interval;
+        self
+    }
+
+    pub fn safe_state(mut self, safe_state: Option<Vec<u8>>) -> Self {
+        self.safe_state = safe_state;
+        self
+    }
+
+    pub fn heartbeat_interval(mut self, heartbeat_interval: Duration) -> Self {
+        self.heartbeat_interval = heartbeat_interval;
+        self
+    }
+}
+
+/// SimulatorType:TheBus, sent to every sink at start
+pub fn init_frame() -> Vec<u8> {
+    let mut init_buffer = Vec::new();
+    let simulator_type = KomsiCommand::SimulatorType(1);
+    // serialze simulator_type into buffer
+    init_buffer.extend_from_slice(&KomsiCommand::build(&simulator_type));
+    // hänge ein "\n" NEW-LINE an den Buffer
+    init_buffer.extend_from_slice(&KomsiCommand::build_eol());
+    init_buffer
+}
+
+/// SimulatorType:none, sent to every sink while TheBus is not running
+pub fn not_running_frame() -> Vec<u8> {
+    let mut frame = KomsiCommand::build(&KomsiCommand::SimulatorType(0));
+    frame.extend_from_slice(&KomsiCommand::build_eol());
+    frame
+}
+
+/// All lamps off, gauges at zero, ignition off
+pub const DEFAULT_SAFE_STATE: &str = "A0B0C0D0E0F0G0H0I0J0K0L0M0N0P0s0t0u0v0w0x0y0z0";
+
+/// Builds the frame of a safe state like `A0B0y0`, every command is checked.
+pub fn safe_state_frame(s: &str) -> Result<Vec<u8>, String> {
+    let commands = split_frame(s.as_bytes());
+    if commands.is_empty() {
+        return Err("no KOMSI commands".to_string());
+    }
+
+    let mut frame = Vec::new();
+    for (code, digits) in commands {
+        let command = format!("{}{}", code, digits);
+        let cmd: KomsiCommand = command
+            .parse()
+            .map_err(|_| format!("{} is not a KOMSI command", command))?;
+        frame.extend_from_slice(&KomsiCommand::build(&cmd));
+    }
+    frame.extend_from_slice(&KomsiCommand::build_eol());
+    Ok(frame)
+}
+
+/// How long `close` waits for the sinks to write their last frames
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Closes the sinks, those which still write in a task get a moment for it.
+pub async fn close_sinks(sinks: Vec<Box<dyn KomsiSink>>, verbose: bool) {
+    let mut closing = Vec::new();
+    for mut sink in sinks {
+        match sink.close() {
+            Ok(()) if verbose => println!("{} closed.", sink.name()),
+            Ok(()) => {}
+            Err(e) => eprintln!("Error closing {}: {}", sink.name(), e),
+        }
+        closing.extend(sink.closing().map(|task| (sink.name(), task)));
+    }
+
+    for (name, task) in closing {
+        if timeout(CLOSE_TIMEOUT, task).await.is_err() {
+            eprintln!("{} did not finish in time.", name);
+        }
+    }
+}
+
+/// What the bridge did, printed when it stops
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    pub running_time: Duration,
+    pub polls: u64,
+    pub frames_sent: u64,
+    pub api_errors: u64,
+    pub actions: u64,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.running_time.as_secs();
+        write!(
+            f,
+            "Ran {}:{:02}:{:02}, {} polls, {} frames sent, {} API errors, {} actions.",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            self.polls,
+            self.frames_sent,
+            self.api_errors,
+            self.actions
+        )
+    }
+}
+
+/// Sinks opened by a reload, with their keys
+pub type OpenSinks = Pin<Box<dyn Future<Output = Vec<(String, Box<dyn KomsiSink>)>> + Send>>;
+
+/// A changed config, applied by `run_until` after the current poll
+pub struct Reload {
+    pub options: BridgeOptions,
+    pub input_map: InputMap,
+    pub profiles: Profiles,
+    /// keys of the sinks to close, a changed sink is closed and opened again
+    pub close: Vec<String>,
+    /// opens the new sinks, after the old ones were closed, so they can use the same ports
+    pub open: OpenSinks,
+}
+
+pub struct Bridge<S: TelemetrySource> {
+    source: S,
+    sinks: Vec<Box<dyn KomsiSink>>,
+    /// key of each sink (same order), empty if it cannot be replaced by a reload
+    sink_keys: Vec<String>,
+    reloads: Option<mpsc::UnboundedReceiver<Reload>>,
+    input_map: InputMap,
+    profiles: Profiles,
+    options: BridgeOptions,
+
+    vehicle_name: String,
+    old_vehicle_name: String,
+    vehicle_model: String,
+    vehicle_name_timer: Timer,
+    world_timer: Timer,
+    slow_timer: Timer,
+    backoff: Backoff,
+    /// kind of the last error and its backoff, None after a success
+    error_backoff: Option<(String, Backoff)>,
+    /// the poll waited on purpose, it is not late
+    slept: bool,
+    /// false after the API was not reachable, the devices got the not running frame
+    thebus_running: bool,
+    /// the devices show the safe state, nothing was sent since
+    in_safe_state: bool,
+    heartbeat_timer: Timer,
+    vehicle_state: VehicleState,
+    extra_values: ExtraValues,
+    force_all_variables: bool,
+    /// index into profiles.profiles
+    profile: Option<usize>,
+    mapping: Mapping,
+    /// actions of the current vehicle, only used to warn about unknown input mappings
+    vehicle_actions: Vec<String>,
+    started: Instant,
+    summary: Summary,
+}
+
+impl<S: TelemetrySource> Bridge<S> {
+    pub fn new(source: S) -> Self {
+        let options = BridgeOptions::default();
+        Self {
+            source,
+            sinks: Vec::new(),
+            sink_keys: Vec::new(),
+            reloads: None,
+            input_map: InputMap::new(),
+            profiles: Profiles::new(),
+            vehicle_name: String::new(),
+            old_vehicle_name: String::new(),
+            vehicle_model: String::new(),
+            vehicle_name_timer: Timer::new(options.vehicle_name_interval),
+            world_timer: Timer::new(options.world_interval),
+            slow_timer: Timer::new(options.slow_interval),
+            backoff: Backoff::new(options.error_interval, options.max_error_interval),
+            error_backoff: None,
+            slept: false,
+            thebus_running: true,
+            in_safe_state: true,
+            heartbeat_timer: Timer::new(options.heartbeat_interval),
+            vehicle_state: VehicleState::new(),
+            extra_values: ExtraValues::new(),
+            force_all_variables: false,
+            profile: None,
+            mapping: Mapping::new(),
+            vehicle_actions: Vec::new(),
+            started: Instant::now(),
+            summary: Summary::default(),
+            options,
+        }
+    }
+
+    pub fn options(mut self, options: BridgeOptions) -> Self {
+        self.vehicle_name_timer = Timer::new(options.vehicle_name_interval);
+        self.world_timer = Timer::new(options.world_interval);
+        self.slow_timer = Timer::new(options.slow_interval);
+        self.backoff = Backoff::new(options.error_interval, options.max_error_interval);
+        self.heartbeat_timer = Timer::new(options.heartbeat_interval);
+        self.options = options;
+        self
+    }
+
+    pub fn sink(self, sink: Box<dyn KomsiSink>) -> Self {
+        self.keyed_sink("", sink)
+    }
+
+    /// A sink which a reload can close by its key, e.g. `port.gauges`
+    pub fn keyed_sink(mut self, key: &str, sink: Box<dyn KomsiSink>) -> Self {
+        self.sinks.push(sink);
+        self.sink_keys.push(key.to_string());
+        self
+    }
+
+    /// Changed configs to apply while running
+    pub fn reloads(mut self, reloads: mpsc::UnboundedReceiver<Reload>) -> Self {
+        self.reloads = Some(reloads);
+        self
+    }
+
+    pub fn input_map(mut self, input_map: InputMap) -> Self {
+        self.input_map = input_map;
+        self
+    }
+
+    pub fn profiles(mut self, profiles: Profiles) -> Self {
+        self.mapping = profiles.mapping(None);
+        self.profiles = profiles;
+        self
+    }
+
+    /// The KOMSI state sent to the sinks last
+    pub fn vehicle_state(&self) -> &VehicleState {
+        &self.vehicle_state
+    }
+
+    pub fn vehicle_name(&self) -> &str {
+        &self.vehicle_name
+    }
+
+    /// False while the telemetry API of TheBus is not reachable
+    pub fn thebus_running(&self) -> bool {
+        self.thebus_running
+    }
+
+    /// What the bridge did so far
+    pub fn summary(&self) -> Summary {
+        Summary {
+            running_time: self.started.elapsed(),
+            ..self.summary.clone()
+        }
+    }
+
+    /// Sends the init frame and polls until the source is finished.
+    pub async fn run(&mut self) {
+        self.run_until(std::future::pending()).await;
+    }
+
+    /// Like `run`, but stops when `shutdown` completes (e.g. Ctrl-C),
+    /// then the devices get the safe state. The sinks stay open, see `close`.
+    pub async fn run_until(&mut self, shutdown: impl Future<Output = ()>) {
+        self.send_to_sinks(&init_frame());
+        self.heartbeat_timer.done();
+
+        let mut pacer = Pacer::new(self.options.interval);
+        let mut reloads = self.reloads.take();
+        tokio::pin!(shutdown);
+
+        while !self.source.finished() {
+            tokio::select! {
+                _ = &mut shutdown => {
+                    self.enter_safe_state();
+                    return;
+                }
+                _ = self.poll(&mut pacer) => {}
+            }
+
+            if let Some((overruns, worst)) = pacer.overrun_report() {
+                eprintln!(
+                    "{} polls took longer than {} ms (up to {} ms late), increase sleeptime?",
+                    overruns,
+                    self.options.interval.as_millis(),
+                    worst.as_millis()
+                );
+            }
+
+            // between two polls, a poll is never interrupted by a reload
+            while let Some(reload) = reloads.as_mut().and_then(|r| r.try_recv().ok()) {
+                let interval = self.options.interval;
+                self.reload(reload).await;
+                if self.options.interval != interval {
+                    pacer = Pacer::new(self.options.interval);
+                }
+            }
+        }
+    }
+
+    /// Applies a changed config: the timers with a changed interval start anew, the mapping
+    /// of the current vehicle is built again and all values are sent with the next poll.
+    /// The sinks to close are closed before the new ones are opened.
+    pub async fn reload(&mut self, reload: Reload) {
+        let old = std::mem::replace(&mut self.options, reload.options);
+        if old.vehicle_name_interval != self.options.vehicle_name_interval {
+            self.vehicle_name_timer = Timer::new(self.options.vehicle_name_interval);
+        }
+        if old.world_interval != self.options.world_interval {
+            self.world_timer = Timer::new(self.options.world_interval);
+        }
+        if old.slow_interval != self.options.slow_interval {
+            self.slow_timer = Timer::new(self.options.slow_interval);
+        }
+        if old.heartbeat_interval != self.options.heartbeat_interval {
+            self.heartbeat_timer = Timer::new(self.options.heartbeat_interval);
+        }
+        if old.error_interval != self.options.error_interval
+            || old.max_error_interval != self.options.max_error_interval
+        {
+            self.backoff = Backoff::new(self.options.error_interval, self.options.max_error_interval);
+            self.error_backoff = None;
+        }
+
+        self.input_map = reload.input_map;
+        self.profiles = reload.profiles;
+        self.profile = if self.vehicle_name.is_empty() {
+            None
+        } else {
+            self.profiles
+                .profiles
+                .iter()
+                .position(|p| p.matches(&self.vehicle_model, &self.vehicle_name))
+        };
+        self.mapping = self
+            .profiles
+            .mapping(self.profile.map(|i| &self.profiles.profiles[i]));
+        self.force_all_variables = true;
+
+        let mut closed = Vec::new();
+        let mut i = 0;
+        while i < self.sinks.len() {
+            if reload.close.contains(&self.sink_keys[i]) {
+                self.sink_keys.remove(i);
+                closed.push(self.sinks.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        close_sinks(closed, self.options.verbose).await;
+
+        // the new sinks missed the init frame, the state follows with the next poll
+        let frame = if self.thebus_running {
+            init_frame()
+        } else {
+            not_running_frame()
+        };
+        for (key, mut sink) in reload.open.await {
+            if let Err(e) = sink.send(&frame)
+                && e.kind() != std::io::ErrorKind::NotConnected
+            {
+                eprintln!("Error writing to port {}: {}", sink.name(), e);
+            }
+            self.sinks.push(sink);
+            self.sink_keys.push(key);
+        }
+    }
+
+    /// One poll and the wait for the next one. A poll which waited for a bus
+    /// is not late, the rate starts again after it.
+    pub async fn poll(&mut self, pacer: &mut Pacer) {
+        self.step().await;
+        if std::mem::take(&mut self.slept) {
+            pacer.reset();
+        } else {
+            pacer.wait().await;
+        }
+    }
+
+    /// Sends the safe state unless the devices already show it.
+    /// The next bus starts from scratch and gets the whole state.
+    pub fn enter_safe_state(&mut self) {
+        if self.in_safe_state {
+            return;
+        }
+        self.in_safe_state = true;
+        self.vehicle_state = VehicleState::new();
+        self.extra_values = ExtraValues::new();
+        self.force_all_variables = true;
+
+        if let Some(frame) = self.options.safe_state.clone() {
+            if self.options.verbose {
+                println!("Sending safe state.");
+            }
+            self.send_to_sinks(&frame);
+        }
+    }
+
+    /// Flushes and releases all sinks, nothing can be sent afterwards.
+    /// Sinks which still write in a task get a moment for it.
+    pub async fn close(&mut self) -> Summary {
+        self.sink_keys.clear();
+        close_sinks(std::mem::take(&mut self.sinks), self.options.verbose).await;
+        self.summary()
+    }
+
+    /// Sends a frame to all sinks.
+    pub fn send_to_sinks(&mut self, frame: &[u8]) {
+        self.summary.frames_sent += 1;
+        for sink in self.sinks.iter_mut() {
+            match sink.send(frame) {
+                Ok(()) => {}
+                // the sink has already reported why it is not connected
+                Err(e) if e.kind() == std::io::ErrorKind::NotConnected => {}
+                Err(e) => eprintln!("Error writing to port {}: {}", sink.name(), e),
+            }
+        }
+    }
+
+    /// One poll of the telemetry source.
+    pub async fn step(&mut self) {
+        let verbose = self.options.verbose;
+        self.summary.polls += 1;
+
+        let mut name_error = None;
+        if self.vehicle_name.is_empty() || self.vehicle_name_timer.due() {
+            self.vehicle_name_timer.done();
+            match self.source.current_vehicle_name().await {
+                Ok(name) => {
+                    self.vehicle_name = name;
+                    self.api_ok();
+                }
+                Err(e) => {
+                    self.vehicle_name.clear();
+                    name_error = Some(e);
+                }
+            }
+        }
+
+        if let Some(e) = name_error {
+            self.api_error("Error getting the vehicle name", e).await;
+        } else if self.vehicle_name.is_empty() {
+            println!("No vehicle found, not in bus.");
+            self.world_timer.reset();
+            self.force_all_variables = true;
+            sleep(self.backoff.next_wait()).await;
+            self.slept = true;
+        } else {
+            self.backoff.reset();
+        }
+
+        if self.options.debug {
+            println!("Vehicle-Name: {}", self.vehicle_name);
+        }
+
+        let (vehicle, vehicle_json) = if self.vehicle_name.is_empty() {
+            (ApiVehicleType::new(), Value::Null)
+        } else {
+            let new_vehicle = self.vehicle_name != self.old_vehicle_name;
+            match self.get_vehicle(new_vehicle).await {
+                Ok(response) => {
+                    self.api_ok();
+                    response
+                }
+                Err(e) => {
+                    self.vehicle_name.clear();
+                    self.api_error("Error getting vehicle data", e).await;
+                    (ApiVehicleType::new(), Value::Null)
+                }
+            }
+        };
+
+        if self.vehicle_model != vehicle.vehicle_model {
+            self.vehicle_model = vehicle.vehicle_model.clone();
+        }
+
+        if verbose && self.old_vehicle_name.is_empty() && !self.vehicle_name.is_empty() {
+            println!("Hingesetzt. Jetzt gehts los!");
+        }
+
+        if self.vehicle_name != self.old_vehicle_name {
+            if verbose {
+                println!(
+                    "Vehicle is now: model={} name={}",
+                    self.vehicle_model, self.vehicle_name
+                );
+            }
+
+            // left the bus or lost the telemetry, the dashboard must not freeze
+            // and the profile of the bus is not used anymore
+            if self.vehicle_name.is_empty() {
+                self.enter_safe_state();
+                self.profile = None;
+            }
+
+            self.old_vehicle_name = self.vehicle_name.clone();
+            for sink in self.sinks.iter_mut() {
+                sink.vehicle_changed(&self.vehicle_name);
+            }
+            self.mapping = self
+                .profiles
+                .mapping(self.profile.map(|i| &self.profiles.profiles[i]));
+        }
+
+        if !self.input_map.is_empty() {
+            self.vehicle_actions = vehicle
+                .buttons
+                .iter()
+                .flat_map(|b| b.actions.iter().cloned())
+                .collect();
+        }
+
+        // button actions received from the dashboard
+        self.handle_input().await;
+
+        // now we can process
+
+        let mut new_vehicle_state = {
+            if self.vehicle_name.is_empty() {
+                VehicleState::new()
+            } else {
+                get_vehicle_state_from_api(vehicle)
+            }
+        };
+
+        let mut new_extra_values = self.mapping.apply(&vehicle_json, &mut new_vehicle_state);
+
+        // slow values are only taken every slow_interval, entering a bus takes everything
+        if self.force_all_variables || self.slow_timer.due() {
+            self.slow_timer.done();
+        } else {
+            keep_slow_values(
+                &self.vehicle_state,
+                &mut new_vehicle_state,
+                &self.extra_values,
+                &mut new_extra_values,
+            );
+        }
+
+        if self.options.debug {
+            new_vehicle_state.print();
+        }
+        new_vehicle_state.datetime = self.vehicle_state.datetime;
+
+        // ONLY every world_interval but only if we have a valid vehicle
+        if !self.vehicle_name.is_empty() && self.world_timer.due() {
+            self.world_timer.done();
+
+            // now we check the world
+            match self.get_world().await {
+                Ok(api_world) => {
+                    if let Ok(komsi_date_time) = KomsiDateTime::from_iso(&api_world.date_time) {
+                        new_vehicle_state.datetime = komsi_date_time;
+                    }
+                }
+                Err(e) => println!("Error getting world data: {}", e),
+            }
+        }
+
+        // compare and create cmd buf
+        let mut logger: Option<&dyn VehicleLogger> = None;
+        if verbose {
+            logger = Some(&PrintLogger);
+        }
+        let mut cmdbuf = Vec::new();
+
+        if !self.vehicle_name.is_empty() {
+            cmdbuf =
+                self.vehicle_state
+                    .compare(&new_vehicle_state, self.force_all_variables, logger);
+            let extra_cmds = compare_extra(
+                &self.extra_values,
+                &new_extra_values,
+                self.force_all_variables,
+                logger,
+            );
+            append_commands(&mut cmdbuf, &extra_cmds);
+            self.force_all_variables = false;
+            // replace after compare for next round
+            self.vehicle_state = new_vehicle_state;
+            self.extra_values = new_extra_values;
+        }
+
+        // we only send, when we have a vehicle name
+        if !cmdbuf.is_empty() {
+            if self.options.debug_serial {
+                println!("SENDING -> {:?}", cmdbuf);
+            }
+
+            self.send_to_sinks(&cmdbuf);
+            self.in_safe_state = false;
+        }
+
+        if !self.options.heartbeat_interval.is_zero() && self.heartbeat_timer.due() {
+            self.heartbeat_timer.done();
+            let frame = if self.thebus_running {
+                init_frame()
+            } else {
+                not_running_frame()
+            };
+            self.send_to_sinks(&frame);
+        }
+
+        self.refresh_reconnected_sinks();
+    }
+
+    /// The whole state as one frame, like after entering a bus
+    pub fn full_state_frame(&self) -> Vec<u8> {
+        let mut frame = VehicleState::new().compare(&self.vehicle_state, true, None);
+        let extra_cmds = compare_extra(&ExtraValues::new(), &self.extra_values, true, None);
+        append_commands(&mut frame, &extra_cmds);
+        frame
+    }
+
+    // A device which was connected again only got the init frame from its sink,
+    // it gets the whole state, the other sinks are not bothered.
+    fn refresh_reconnected_sinks(&mut self) {
+        let mut frame = None;
+
+        for i in 0..self.sinks.len() {
+            // without a vehicle there is no state, entering a bus sends everything anyway
+            if !self.sinks[i].reconnected() || self.vehicle_name.is_empty() {
+                continue;
+            }
+
+            let frame = frame.get_or_insert_with(|| self.full_state_frame());
+            let sink = &mut self.sinks[i];
+
+            if self.options.verbose {
+                println!("Sending whole state to {}.", sink.name());
+            }
+            if let Err(e) = sink.send_full_state(frame)
+                && e.kind() != std::io::ErrorKind::NotConnected
+            {
+                eprintln!("Error writing to port {}: {}", sink.name(), e);
+            }
+        }
+    }
+
+    // The API answered: the backoff starts over, and if TheBus was not running
+    // the devices get the init frame again, the whole state follows with the next vehicle.
+    fn api_ok(&mut self) {
+        self.error_backoff = None;
+
+        if !self.thebus_running {
+            println!("TheBus is running.");
+            self.thebus_running = true;
+            self.send_to_sinks(&init_frame());
+        }
+    }
+
+    // Logs the error with its cause and waits as long as its kind wants.
+    // If TheBus is not running the user and the devices are told once.
+    async fn api_error(&mut self, what: &str, e: TelemetryError) {
+        self.world_timer.reset();
+        self.summary.api_errors += 1;
+
+        if e.is_not_running() {
+            self.force_all_variables = true;
+            if self.thebus_running {
+                println!("TheBus is not running, waiting for it. ({})", e);
+                self.enter_safe_state();
+                self.thebus_running = false;
+                self.send_to_sinks(&not_running_frame());
+            } else if self.options.verbose {
+                println!("{}: {}", what, e);
+            }
+        } else {
+            println!("{}: {}", what, e);
+        }
+
+        let kind = e.kind();
+        let wait = match &mut self.error_backoff {
+            Some((last_kind, backoff)) if *last_kind == kind => backoff.next_wait(),
+            _ => {
+                let mut backoff =
+                    e.backoff(self.options.error_interval, self.options.max_error_interval);
+                let wait = backoff.next_wait();
+                self.error_backoff = Some((kind, backoff));
+                wait
+            }
+        };
+        sleep(wait).await;
+        self.slept = true;
+    }
+
+    // We keep the JSON for the user defined mapping and apply the aliases of the
+    // vehicle profile before the built-in mapping reads it.
+    // A new vehicle selects a new profile.
+    async fn get_vehicle(
+        &mut self,
+        new_vehicle: bool,
+    ) -> Result<(ApiVehicleType, Value), SourceError> {
+        let mut body = self.source.vehicle(&self.vehicle_name).await?;
+
+        if new_vehicle {
+            let vehicle_model = body
+                .get("VehicleModel")
+                .and_then(Value::as_str)
+                .unwrap_or("");
+            self.profile = self
+                .profiles
+                .profiles
+                .iter()
+                .position(|p| p.matches(vehicle_model, &self.vehicle_name));
+            if self.options.verbose {
+                match self.profile {
+                    Some(i) => println!(
+                        "Using profile {} for {}.",
+                        self.profiles.profiles[i].name, vehicle_model
+                    ),
+                    None if !self.profiles.profiles.is_empty() => {
+                        println!("No profile for {}.", vehicle_model)
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        let raw = body.clone();
+        if let Some(i) = self.profile {
+            self.profiles.profiles[i].apply_aliases(&mut body);
+        }
+
+        let api_vehicle: ApiVehicleType = match serde_json::from_value(body.clone()) {
+            Ok(v) => v,
+            Err(e) if self.profile.is_some() => {
+                eprintln!("Aliases of profile not usable ({}), using plain API data.", e);
+                body = raw;
+                serde_json::from_value(body.clone())?
+            }
+            Err(e) => return Err(TelemetryError::Schema(format!("vehicle: {}", e))),
+        };
+
+        if self.options.debug {
+            println!("{:?}", &api_vehicle);
+        }
+
+        Ok((api_vehicle, body))
+    }
+
+    async fn get_world(&mut self) -> Result<ApiWorldType, SourceError> {
+        let body = self.source.world().await?;
+        let api_world: ApiWorldType = serde_json::from_value(body)
+            .map_err(|e| TelemetryError::Schema(format!("world: {}", e)))?;
+
+        if self.options.debug {
+            println!("{:?}", &api_world);
+        }
+
+        Ok(api_world)
+    }
+
+    // KOMSI commands received from the sinks are mapped to actions and sent to TheBus,
+    // together with the actions some sinks request directly
+    async fn handle_input(&mut self) {
+        let mut actions = Vec::new();
+        for sink in self.sinks.iter_mut() {
+            for cmd in sink.receive() {
+                match self.input_map.action(&cmd) {
+                    Some(action) => actions.push(action.to_string()),
+                    None if self.options.debug_serial => {
+                        eprintln!("No input mapping for {:?} from {}", cmd, sink.name());
+                    }
+                    None => {}
+                }
+            }
+            actions.extend(sink.receive_actions());
+        }
+
+        for action in actions {
+            if self.vehicle_name.is_empty() {
+                if self.options.verbose {
+                    println!("Not in bus, action {} ignored.", action);
+                }
+                continue;
+            }
+
+            if self.options.verbose && !self.vehicle_actions.contains(&action) {
+                println!(
+                    "Action {} is not offered by {}.",
+                    action, self.vehicle_model
+                );
+            }
+
+            if self.options.debug_command {
+                println!("ACTION -> {}", action);
+            }
+
+            match self.source.send_action(&self.vehicle_name, &action).await {
+                Ok(()) => self.summary.actions += 1,
+                Err(e) => eprintln!("Error sending action {}: {}", action, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,317 @@
+// Checks of TheBus2Komsi.ini for --check-config
+//
+// Config::read collects what it cannot use, this adds what it silently ignores
+// (unknown sections and keys, keys given twice) and what only shows at runtime
+// (ports used twice or not there, TheBus not reachable), each with its line.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use the_bus_telemetry::api::RequestConfig;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use crate::config::{Config, PortConfig, Problem, Source, PORT_SECTION_PREFIX};
+use crate::input::INPUT_SECTION;
+use crate::mapping::MAPPING_SECTION;
+use crate::mqtt::MQTT_SECTION;
+use crate::network::{NetworkTarget, NETWORK_SECTION};
+use crate::profile::PROFILE_SECTION_PREFIX;
+use crate::serial::{port_exists, same_port_name, PortSelector};
+use crate::web::WEB_SECTION;
+
+/// How long the TheBus API may take to accept the connection
+const API_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Something wrong in the config file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    /// Line in the file, None if it is about the whole file
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Where the sections and keys are, configparser does not keep the lines
+#[derive(Debug, Default)]
+pub struct IniLines {
+    /// section name and its first line
+    sections: Vec<(String, usize)>,
+    /// section, key and line, in the order of the file
+    keys: Vec<(String, String, usize)>,
+}
+
+impl IniLines {
+    pub fn parse(text: &str) -> Self {
+        let mut lines = Self::default();
+        // like configparser, keys before the first section belong to [default]
+        let mut section = "default".to_string();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_lowercase();
+                if lines.section(&section).is_none() {
+                    lines.sections.push((section.clone(), n + 1));
+                }
+                continue;
+            }
+
+            let key = line.split(['=', ':']).next().unwrap_or("").trim();
+            let key = if case_sensitive(&section) {
+                key.to_string()
+            } else {
+                key.to_lowercase()
+            };
+            lines.keys.push((section.clone(), key, n + 1));
+        }
+
+        lines
+    }
+
+    pub fn section(&self, section: &str) -> Option<usize> {
+        self.sections
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(section))
+            .map(|(_, line)| *line)
+    }
+
+    /// The last line of the key, a key given twice has the value of its last line
+    pub fn key(&self, section: &str, key: &str) -> Option<usize> {
+        self.keys
+            .iter()
+            .rev()
+            .find(|(s, k, _)| s.eq_ignore_ascii_case(section) && k == key)
+            .map(|(_, _, line)| *line)
+    }
+
+    pub fn problem(&self, problem: &Problem) -> Option<usize> {
+        problem
+            .key
+            .as_deref()
+            .and_then(|key| self.key(&problem.section, key))
+            .or_else(|| self.section(&problem.section))
+    }
+}
+
+/// KOMSI codes and API names are case sensitive, see `Config::read`
+fn case_sensitive(section: &str) -> bool {
+    section == INPUT_SECTION || section == MAPPING_SECTION || section.starts_with(PROFILE_SECTION_PREFIX)
+}
+
+/// `key`, `key2` ... `key5`, like the numbered ports in `Config::read`
+fn numbered(key: &str) -> Vec<String> {
+    (1..=5)
+        .map(|n| if n == 1 { key.to_string() } else { format!("{}{}", key, n) })
+        .collect()
+}
+
+/// The keys of a section, None if any key is allowed
+fn known_keys(section: &str) -> Option<Vec<String>> {
+    let fixed = |keys: &[&str]| keys.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+
+    let keys = match section {
+        "default" => {
+            let mut keys = fixed(&[
+                "ip",
+                "baudrate",
+                "sleeptime",
+                "slowtime",
+                "worldtime",
+                "vehiclenametime",
+                "heartbeat",
+                "safestate",
+            ]);
+            for key in ["portname", "filter", "usb", "usbfilter"] {
+                keys.extend(numbered(key));
+            }
+            keys
+        }
+        NETWORK_SECTION => NetworkTarget::KEYS
+            .iter()
+            .flat_map(|kind| [numbered(kind), numbered(&format!("{}filter", kind))])
+            .flatten()
+            .collect(),
+        WEB_SECTION => fixed(&["listen"]),
+        MQTT_SECTION => fixed(&["broker", "topic", "clientid", "username", "password", "filter"]),
+        _ if section.starts_with(PORT_SECTION_PREFIX) => fixed(&[
+            "device",
+            "usb",
+            "baudrate",
+            "databits",
+            "parity",
+            "flowcontrol",
+            "dtr",
+            "rts",
+            "enabled",
+            "filter",
+        ]),
+        _ => return None,
+    };
+    Some(keys)
+}
+
+fn known_section(section: &str) -> bool {
+    known_keys(section).is_some()
+        || section == INPUT_SECTION
+        || section == MAPPING_SECTION
+        || section.starts_with(PROFILE_SECTION_PREFIX)
+}
+
+/// The config file and what is wrong with it
+#[derive(Debug)]
+pub struct ConfigCheck {
+    pub config: Config,
+    pub findings: Vec<Finding>,
+    lines: IniLines,
+}
+
+impl ConfigCheck {
+    /// Reads the file and checks everything that does not need the system:
+    /// the values, unknown sections and keys, keys given twice and ports used twice.
+    pub fn read(path: &Path) -> Self {
+        let config = Config::read(path, false);
+        let lines = IniLines::parse(&std::fs::read_to_string(path).unwrap_or_default());
+        let mut check = Self {
+            config,
+            findings: Vec::new(),
+            lines,
+        };
+
+        for problem in &check.config.problems {
+            check.findings.push(Finding {
+                line: check.lines.problem(problem),
+                message: problem.message.clone(),
+            });
+        }
+
+        for (section, line) in &check.lines.sections {
+            if !known_section(section) {
+                check.findings.push(Finding {
+                    line: Some(*line),
+                    message: format!("unknown section [{}]", section),
+                });
+            }
+        }
+
+        let mut seen: HashMap<(&str, &str), usize> = HashMap::new();
+        for (section, key, line) in &check.lines.keys {
+            if let Some(first) = seen.insert((section, key), *line) {
+                check.findings.push(Finding {
+                    line: Some(*line),
+                    message: format!("[{}] {} is already set in line {}, this one wins", section, key, first),
+                });
+            }
+            if let Some(keys) = known_keys(section)
+                && !keys.contains(key)
+            {
+                check.findings.push(Finding {
+                    line: Some(*line),
+                    message: format!("unknown key {} in [{}]", key, section),
+                });
+            }
+        }
+
+        let ports = check.enabled_ports();
+        for (i, port) in ports.iter().enumerate() {
+            if let Some(other) = ports[..i].iter().find(|p| same_port(&p.selector, &port.selector)) {
+                check.findings.push(Finding {
+                    line: check.port_line(port),
+                    message: format!("{} is the same port as {}: {}", port.name, other.name, port.selector),
+                });
+            }
+        }
+
+        check
+    }
+
+    /// Ports of the file which are not there, and USB devices on a port used by name
+    pub fn check_ports(&mut self) {
+        let ports = self.enabled_ports();
+        for port in &ports {
+            let line = self.port_line(port);
+            match &port.selector {
+                PortSelector::Name(name) if !port_exists(name) => self.findings.push(Finding {
+                    line,
+                    message: format!("{}: port {} does not exist", port.name, name),
+                }),
+                PortSelector::Name(_) => {}
+                PortSelector::Usb(id) => match id.find_port() {
+                    None => self.findings.push(Finding {
+                        line,
+                        message: format!("{}: USB device {} is not plugged in", port.name, id),
+                    }),
+                    Some(found) => {
+                        let found = PortSelector::Name(found);
+                        if let Some(other) = ports.iter().find(|p| same_port(&p.selector, &found)) {
+                            self.findings.push(Finding {
+                                line,
+                                message: format!(
+                                    "{} is the same port as {}: USB device {} is on {}",
+                                    port.name, other.name, id, found
+                                ),
+                            });
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    /// The telemetry API of TheBus must accept connections at `ip`
+    pub async fn check_api(&mut self) {
+        let address = format!("{}:{}", self.config.ip, RequestConfig::new().port);
+        let error = match timeout(API_TIMEOUT, TcpStream::connect(&address)).await {
+            Ok(Ok(_)) => return,
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => "timeout".to_string(),
+        };
+        self.findings.push(Finding {
+            line: self.lines.key("default", "ip"),
+            message: format!(
+                "TheBus API at {} is not reachable ({}), is TheBus running with the telemetry API enabled?",
+                address, error
+            ),
+        });
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    // The default port COM1 is no setting of the file
+    fn enabled_ports(&self) -> Vec<PortConfig> {
+        if !matches!(self.config.sources.get("ports"), Some(Source::File(_))) {
+            return Vec::new();
+        }
+        self.config.ports.iter().filter(|p| p.enabled).cloned().collect()
+    }
+
+    fn port_line(&self, port: &PortConfig) -> Option<usize> {
+        self.lines
+            .key("default", &port.name)
+            .or_else(|| self.lines.section(&format!("{}{}", PORT_SECTION_PREFIX, port.name)))
+    }
+}
+
+fn same_port(a: &PortSelector, b: &PortSelector) -> bool {
+    match (a, b) {
+        (PortSelector::Name(a), PortSelector::Name(b)) => same_port_name(a, b),
+        (PortSelector::Usb(a), PortSelector::Usb(b)) => a == b,
+        _ => false,
+    }
+}
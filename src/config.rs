@@ -0,0 +1,747 @@
+// Settings of TheBus2Komsi.ini
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use configparser::ini::Ini;
+
+use crate::bridge::{safe_state_frame, DEFAULT_SAFE_STATE};
+use crate::filter::CommandFilter;
+use crate::input::InputMap;
+use crate::mqtt::MqttConfig;
+use crate::network::{NetworkTarget, NETWORK_SECTION};
+use crate::profile::Profiles;
+use crate::serial::{LineMode, PortSelector, PortSettings, UsbId};
+use crate::web::WEB_SECTION;
+
+/// Name of the config file, see `find_config_file` for where it is searched
+pub const CONFIG_FILE: &str = "TheBus2Komsi.ini";
+
+/// Directory of the config file in the user config directory
+const USER_CONFIG_DIR: &str = "TheBus2Komsi";
+
+/// Environment variables which override the config file, the command line wins over them
+pub const ENV_CONFIG: &str = "THEBUS2KOMSI_CONFIG";
+pub const ENV_IP: &str = "THEBUS2KOMSI_IP";
+pub const ENV_PORTS: &str = "THEBUS2KOMSI_PORTS";
+pub const ENV_BAUDRATE: &str = "THEBUS2KOMSI_BAUDRATE";
+pub const ENV_SLEEPTIME: &str = "THEBUS2KOMSI_SLEEPTIME";
+
+/// `[port.<name>]` sections configure one serial port each
+pub const PORT_SECTION_PREFIX: &str = "port.";
+
+#[derive(Debug)]
+pub struct Config {
+    /// File the config was read from, None if the defaults are used
+    pub path: Option<PathBuf>,
+    pub ip: String,
+    /// Baudrate of ports without their own
+    pub baudrate: u32,
+    /// Poll interval in ms
+    pub sleeptime: u64,
+    /// Interval of slow values like fuel in ms
+    pub slowtime: u64,
+    /// Interval of the world (date and time) in ms
+    pub worldtime: u64,
+    /// Interval of the vehicle name in ms
+    pub vehiclenametime: u64,
+    /// Frame sent when there is no bus to show, None: nothing is sent
+    pub safe_state: Option<Vec<u8>>,
+    /// Interval of the heartbeat in ms, 0: no heartbeat
+    pub heartbeat: u64,
+    /// Ports of the dashboards, by name (`portname`) or by USB device (`usb`),
+    /// and those of the `[port.<name>]` sections
+    pub ports: Vec<PortConfig>,
+    /// Dashboards connected over the network
+    pub network: Vec<NetworkConfig>,
+    /// Address of the web server with the state as JSON, None: no web server
+    pub web: Option<String>,
+    /// MQTT broker for home cockpits, None: no MQTT
+    pub mqtt: Option<MqttConfig>,
+    pub input_map: InputMap,
+    pub profiles: Profiles,
+    /// Where ip, ports, baudrate and sleeptime came from, shown at start
+    pub sources: BTreeMap<&'static str, Source>,
+    /// Entries of the file which were ignored because they cannot be used
+    pub problems: Vec<Problem>,
+}
+
+/// An entry of the config file which cannot be used
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// Section of the entry, empty if the file cannot be read at all
+    pub section: String,
+    /// None if the whole section is ignored
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn key(section: &str, key: &str, message: String) -> Self {
+        Self {
+            section: section.to_string(),
+            key: Some(key.to_string()),
+            message,
+        }
+    }
+
+    pub fn section(section: &str, message: String) -> Self {
+        Self {
+            section: section.to_string(),
+            key: None,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Where a setting came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    /// name of the environment variable
+    Env(&'static str),
+    CommandLine,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Env(var) => write!(f, "environment {}", var),
+            Source::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+/// Settings from the command line or the environment, they win over the config file
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub ip: Option<(String, Source)>,
+    /// port names or USB ids (`VID:PID[:serial]`), they replace all configured ports
+    pub ports: Option<(Vec<String>, Source)>,
+    /// for all ports
+    pub baudrate: Option<(u32, Source)>,
+    pub sleeptime: Option<(u64, Source)>,
+}
+
+impl Overrides {
+    /// Reads the environment variables, `env` is `std::env::var` outside of tests.
+    /// Values which are not usable are reported and ignored.
+    pub fn from_env(env: impl Fn(&str) -> Option<String>) -> Self {
+        let value = |var: &'static str| {
+            env(var)
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        let number = |var: &'static str| {
+            value(var).and_then(|v| match v.parse() {
+                Ok(n) => Some(n),
+                Err(_) => {
+                    eprintln!("{} ignored, {} is not a number", var, v);
+                    None
+                }
+            })
+        };
+
+        Self {
+            ip: value(ENV_IP).map(|ip| (ip, Source::Env(ENV_IP))),
+            ports: value(ENV_PORTS).map(|ports| {
+                let ports = ports
+                    .split(',')
+                    .map(|p| p.trim().to_string())
+                    .filter(|p| !p.is_empty())
+                    .collect();
+                (ports, Source::Env(ENV_PORTS))
+            }),
+            baudrate: number(ENV_BAUDRATE)
+                .and_then(|b| {
+                    u32::try_from(b)
+                        .map_err(|_| eprintln!("{} ignored, {} is too large", ENV_BAUDRATE, b))
+                        .ok()
+                })
+                .map(|b| (b, Source::Env(ENV_BAUDRATE))),
+            sleeptime: number(ENV_SLEEPTIME).map(|t| (t, Source::Env(ENV_SLEEPTIME))),
+        }
+    }
+}
+
+/// Where the config file is searched, in this order:
+/// the directory of the executable, the user config directory
+/// (`%APPDATA%\TheBus2Komsi` or `~/.config/TheBus2Komsi`) and the working directory.
+pub fn config_search_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    if let Ok(exe) = std::env::current_exe()
+        && let Some(dir) = exe.parent()
+    {
+        paths.push(dir.join(CONFIG_FILE));
+    }
+
+    let user_dir = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    if let Some(dir) = user_dir {
+        paths.push(dir.join(USER_CONFIG_DIR).join(CONFIG_FILE));
+    }
+
+    paths.push(PathBuf::from(CONFIG_FILE));
+    paths
+}
+
+/// The config file to use: the one given on the command line (or in THEBUS2KOMSI_CONFIG),
+/// which must exist, or the first one found in `config_search_paths`.
+pub fn find_config_file(given: Option<&Path>) -> Result<Option<PathBuf>, String> {
+    if let Some(path) = given {
+        return if path.is_file() {
+            Ok(Some(path.to_path_buf()))
+        } else {
+            Err(format!("Config file {} not found.", path.display()))
+        };
+    }
+
+    Ok(config_search_paths().into_iter().find(|path| path.is_file()))
+}
+
+/// A port, its settings and the KOMSI commands it receives
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortConfig {
+    /// Name of the `[port.<name>]` section, or the key in `[default]` (`portname2`, `usb`, ...)
+    pub name: String,
+    pub selector: PortSelector,
+    pub settings: PortSettings,
+    /// False: configured, but not opened
+    pub enabled: bool,
+    pub filter: CommandFilter,
+}
+
+impl PortConfig {
+    pub fn new(selector: PortSelector) -> Self {
+        Self {
+            name: "portname".to_string(),
+            selector,
+            settings: PortSettings::default(),
+            enabled: true,
+            filter: CommandFilter::all(),
+        }
+    }
+
+    /// Reads a `[port.<name>]` section, the port is selected by `device` or `usb`:
+    ///
+    /// ```ini
+    /// [port.gauges]
+    /// device = COM3
+    /// baudrate = 115200
+    /// databits = 8
+    /// parity = none
+    /// flowcontrol = none
+    /// dtr = off
+    /// rts = keep
+    /// enabled = true
+    /// filter = s, v-z
+    /// ```
+    /// The problem names the key with the bad value, so its line can be shown.
+    pub fn from_section(
+        name: &str,
+        section: &HashMap<String, Option<String>>,
+        baudrate: u32,
+    ) -> Result<Self, Problem> {
+        let value = |key: &str| {
+            section
+                .get(key)
+                .and_then(|v| v.as_deref())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+        };
+        let section_name = format!("{}{}", PORT_SECTION_PREFIX, name);
+        let problem = |key: Option<&str>, e: String| Problem {
+            section: section_name.clone(),
+            key: key.map(str::to_string),
+            message: format!("[{}] ignored: {}", section_name, e),
+        };
+
+        let selector = match (value("device"), value("usb")) {
+            (Some(device), None) => PortSelector::Name(device.to_string()),
+            (None, Some(usb)) => PortSelector::Usb(UsbId::parse(usb).map_err(|e| problem(Some("usb"), e))?),
+            (Some(_), Some(_)) => return Err(problem(Some("usb"), "device and usb are both set".to_string())),
+            (None, None) => return Err(problem(None, "device or usb is missing".to_string())),
+        };
+
+        let mut settings = PortSettings::new(baudrate);
+        if let Some(v) = value("baudrate") {
+            settings.baudrate = v
+                .parse()
+                .map_err(|_| problem(Some("baudrate"), format!("baudrate {} is not a number", v)))?;
+        }
+        if let Some(v) = value("databits") {
+            settings.data_bits = PortSettings::parse_data_bits(v).map_err(|e| problem(Some("databits"), e))?;
+        }
+        if let Some(v) = value("parity") {
+            settings.parity = PortSettings::parse_parity(v).map_err(|e| problem(Some("parity"), e))?;
+        }
+        if let Some(v) = value("flowcontrol") {
+            settings.flow_control =
+                PortSettings::parse_flow_control(v).map_err(|e| problem(Some("flowcontrol"), e))?;
+        }
+        if let Some(v) = value("dtr") {
+            settings.dtr = LineMode::parse(v).map_err(|e| problem(Some("dtr"), e))?;
+        }
+        if let Some(v) = value("rts") {
+            settings.rts = LineMode::parse(v).map_err(|e| problem(Some("rts"), e))?;
+        }
+
+        let enabled = match value("enabled").map(str::to_lowercase).as_deref() {
+            None | Some("true" | "yes" | "on" | "1") => true,
+            Some("false" | "no" | "off" | "0") => false,
+            Some(v) => return Err(problem(Some("enabled"), format!("enabled = {} is not true or false", v))),
+        };
+
+        let filter = match value("filter") {
+            Some(v) => CommandFilter::parse(v).map_err(|e| problem(Some("filter"), e))?,
+            None => CommandFilter::all(),
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            selector,
+            settings,
+            enabled,
+            filter,
+        })
+    }
+}
+
+/// A network sink and the KOMSI commands it receives
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkConfig {
+    pub target: NetworkTarget,
+    pub filter: CommandFilter,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            path: None,
+            ip: "127.0.0.1".to_string(),
+            baudrate: 115200,
+            sleeptime: 200,
+            slowtime: 1000,
+            worldtime: 60000,
+            vehiclenametime: 2000,
+            safe_state: Some(safe_state_frame(DEFAULT_SAFE_STATE).unwrap()),
+            heartbeat: 0,
+            ports: vec![PortConfig::new(PortSelector::Name("COM1".to_string()))],
+            network: Vec::new(),
+            web: None,
+            mqtt: None,
+            input_map: InputMap::new(),
+            profiles: Profiles::new(),
+            sources: ["ip", "ports", "baudrate", "sleeptime"]
+                .into_iter()
+                .map(|key| (key, Source::Default))
+                .collect(),
+            problems: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file, missing values are replaced by defaults.
+    /// Entries which cannot be used are shown and ignored.
+    pub fn load(config_path: &Path, verbose: bool) -> Self {
+        let config = Self::read(config_path, verbose);
+        for problem in &config.problems {
+            eprintln!("{}", problem);
+        }
+        config
+    }
+
+    /// Like `load`, but the entries which cannot be used are only collected in `problems`.
+    pub fn read(config_path: &Path, verbose: bool) -> Self {
+        let mut config = Config::default();
+        let mut problems = Vec::new();
+
+        if !config_path.exists() {
+            if verbose {
+                println!(
+                    "Config file {} not found, using default values IP: {}, portname: COM1, baudrate: {}, sleeptime: {}",
+                    config_path.display(),
+                    config.ip,
+                    config.baudrate,
+                    config.sleeptime
+                );
+            }
+            return config;
+        }
+
+        config.path = Some(config_path.to_path_buf());
+
+        // now we get config ini
+        let mut config_file = Ini::new();
+        if let Err(e) = config_file.load(config_path) {
+            problems.push(Problem::section("", format!("Error reading {}: {}", config_path.display(), e)));
+        }
+
+        // Check for missing configuration values and use defaults if needed
+        let file = Source::File(config_path.to_path_buf());
+
+        match read_uint(&config_file, "baudrate", u32::MAX) {
+            Ok(Some(value)) => {
+                config.baudrate = value;
+                config.sources.insert("baudrate", file.clone());
+            }
+            Ok(None) => {
+                if verbose {
+                    println!("Using default baudrate: {}", config.baudrate);
+                }
+            }
+            Err(e) => problems.push(Problem::key(
+                "default",
+                "baudrate",
+                format!("{}, using {}", e, config.baudrate),
+            )),
+        }
+
+        match read_uint(&config_file, "sleeptime", u64::MAX) {
+            Ok(Some(value)) => {
+                config.sleeptime = value;
+                config.sources.insert("sleeptime", file.clone());
+            }
+            Ok(None) => {
+                if verbose {
+                    println!("Using default sleeptime: {}", config.sleeptime);
+                }
+            }
+            Err(e) => problems.push(Problem::key(
+                "default",
+                "sleeptime",
+                format!("{}, using {}", e, config.sleeptime),
+            )),
+        }
+
+        // the other intervals of the polling schedule
+        for (key, value) in [
+            ("slowtime", &mut config.slowtime),
+            ("worldtime", &mut config.worldtime),
+            ("vehiclenametime", &mut config.vehiclenametime),
+            ("heartbeat", &mut config.heartbeat),
+        ] {
+            match read_uint(&config_file, key, u64::MAX) {
+                Ok(Some(v)) => *value = v,
+                Ok(None) => {}
+                Err(e) => problems.push(Problem::key("default", key, format!("{}, using {}", e, value))),
+            }
+        }
+
+        // what the dashboard shows without a bus, `none` sends nothing
+        match config_file.get("default", "safestate") {
+            Some(value) if value.trim().eq_ignore_ascii_case("none") => config.safe_state = None,
+            Some(value) if !value.trim().is_empty() => match safe_state_frame(&value) {
+                Ok(frame) => config.safe_state = Some(frame),
+                Err(e) => problems.push(Problem::key(
+                    "default",
+                    "safestate",
+                    format!("safestate ignored, using {}: {}", DEFAULT_SAFE_STATE, e),
+                )),
+            },
+            _ => {}
+        }
+
+        let mut ports = Vec::new();
+        for n in 1..=5 {
+            // portname, portname2, ... portname5
+            let suffix = if n == 1 { String::new() } else { n.to_string() };
+
+            if let Some(value) = config_file.get("default", &format!("portname{}", suffix))
+                && !value.is_empty()
+            {
+                let filter = read_filter(&config_file, "default", &format!("filter{}", suffix), &mut problems);
+                ports.push(PortConfig {
+                    name: format!("portname{}", suffix),
+                    settings: PortSettings::new(config.baudrate),
+                    filter,
+                    ..PortConfig::new(PortSelector::Name(value))
+                });
+            }
+        }
+
+        // USB devices are found on whatever port they are plugged in
+        for n in 1..=5 {
+            let suffix = if n == 1 { String::new() } else { n.to_string() };
+            let key = format!("usb{}", suffix);
+
+            if let Some(value) = config_file.get("default", &key)
+                && !value.is_empty()
+            {
+                match UsbId::parse(&value) {
+                    Ok(id) => {
+                        let filter =
+                            read_filter(&config_file, "default", &format!("usbfilter{}", suffix), &mut problems);
+                        ports.push(PortConfig {
+                            name: key,
+                            settings: PortSettings::new(config.baudrate),
+                            filter,
+                            ..PortConfig::new(PortSelector::Usb(id))
+                        });
+                    }
+                    Err(e) => problems.push(Problem::key("default", &key, format!("{} ignored: {}", key, e))),
+                }
+            }
+        }
+
+        // any number of ports with their own settings, sorted by name like the profiles
+        let mut sections: Vec<_> = config_file
+            .get_map_ref()
+            .iter()
+            .filter_map(|(name, section)| {
+                name.strip_prefix(PORT_SECTION_PREFIX)
+                    .map(|port| (port.to_string(), section))
+            })
+            .collect();
+        sections.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (name, section) in sections {
+            match PortConfig::from_section(&name, section, config.baudrate) {
+                Ok(port) => {
+                    if verbose {
+                        println!(
+                            "Port {}: {} {} baud{}",
+                            port.name,
+                            port.selector,
+                            port.settings.baudrate,
+                            if port.enabled { "" } else { " (disabled)" }
+                        );
+                    }
+                    ports.push(port);
+                }
+                Err(problem) => problems.push(problem),
+            }
+        }
+
+        // dashboards on Wi-Fi: tcp, tcpserver, udp and their filters tcpfilter, tcpserverfilter, udpfilter
+        for kind in NetworkTarget::KEYS {
+            for n in 1..=5 {
+                let suffix = if n == 1 { String::new() } else { n.to_string() };
+
+                if let Some(address) = config_file.get(NETWORK_SECTION, &format!("{}{}", kind, suffix))
+                    && !address.is_empty()
+                    && let Some(target) = NetworkTarget::from_key(kind, &address)
+                {
+                    if verbose {
+                        println!("Network: {}", target);
+                    }
+                    let filter_key = format!("{}filter{}", kind, suffix);
+                    config.network.push(NetworkConfig {
+                        target,
+                        filter: read_filter(&config_file, NETWORK_SECTION, &filter_key, &mut problems),
+                    });
+                }
+            }
+        }
+
+        // browser dashboards and overlays
+        if let Some(address) = config_file.get(WEB_SECTION, "listen")
+            && !address.is_empty()
+        {
+            if verbose {
+                println!("Web: {}", address);
+            }
+            config.web = Some(address);
+        }
+
+        config.mqtt = MqttConfig::from_ini(&config_file, &mut problems);
+        if verbose && let Some(mqtt) = &config.mqtt {
+            println!("MQTT: {} topic {}", mqtt.broker, mqtt.topic);
+        }
+
+        if !ports.is_empty() {
+            config.ports = ports;
+            config.sources.insert("ports", file.clone());
+        } else if !config.network.is_empty() || config.web.is_some() || config.mqtt.is_some() {
+            // all dashboards are on the network
+            config.ports.clear();
+        } else {
+            if verbose {
+                println!("Using default portname: COM1");
+            }
+            for port in config.ports.iter_mut() {
+                port.settings.baudrate = config.baudrate;
+            }
+        }
+
+        match config_file.get("default", "ip") {
+            Some(value) => {
+                config.ip = value;
+                config.sources.insert("ip", file);
+            }
+            None => {
+                if verbose {
+                    println!("Using default IP: {}", config.ip);
+                }
+            }
+        }
+
+        // KOMSI codes are case sensitive, so these sections are read again
+        let mut config_file_cs = Ini::new_cs();
+        // the file was already read, an error is reported there
+        let _ = config_file_cs.load(config_path);
+
+        // KOMSI commands from the dashboard which trigger actions in TheBus
+        config.input_map = InputMap::from_ini(&config_file_cs, verbose, &mut problems);
+
+        // user defined API to KOMSI mapping on top of the built-in one, adapted by vehicle profiles
+        config.profiles = Profiles::from_ini(&config_file_cs, verbose, &mut problems);
+
+        config.problems = problems;
+        config
+    }
+
+    /// Settings from the command line or the environment replace those of the file.
+    pub fn apply_overrides(&mut self, overrides: Overrides) {
+        if let Some((ip, source)) = overrides.ip {
+            self.ip = ip;
+            self.sources.insert("ip", source);
+        }
+
+        if let Some((ports, source)) = overrides.ports {
+            self.ports = ports
+                .into_iter()
+                .map(|port| {
+                    // COM3 or /dev/ttyUSB0 are no USB ids
+                    let selector = match UsbId::parse(&port) {
+                        Ok(id) => PortSelector::Usb(id),
+                        Err(_) => PortSelector::Name(port.clone()),
+                    };
+                    PortConfig {
+                        name: port,
+                        settings: PortSettings::new(self.baudrate),
+                        ..PortConfig::new(selector)
+                    }
+                })
+                .collect();
+            self.sources.insert("ports", source);
+        }
+
+        if let Some((baudrate, source)) = overrides.baudrate {
+            self.baudrate = baudrate;
+            for port in self.ports.iter_mut() {
+                port.settings.baudrate = baudrate;
+            }
+            self.sources.insert("baudrate", source);
+        }
+
+        if let Some((sleeptime, source)) = overrides.sleeptime {
+            self.sleeptime = sleeptime;
+            self.sources.insert("sleeptime", source);
+        }
+    }
+
+    /// The main settings and where they came from
+    pub fn report(&self) -> String {
+        let ports = self
+            .ports
+            .iter()
+            .filter(|p| p.enabled)
+            .map(|p| p.selector.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let values = [
+            ("ip", self.ip.clone()),
+            ("ports", if ports.is_empty() { "none".to_string() } else { ports }),
+            ("baudrate", self.baudrate.to_string()),
+            ("sleeptime", self.sleeptime.to_string()),
+        ];
+
+        let mut report = match &self.path {
+            Some(path) => format!("Config file: {}", path.display()),
+            None => "No config file, using default values.".to_string(),
+        };
+        for (key, value) in values {
+            let source = self.sources.get(key).unwrap_or(&Source::Default);
+            report.push_str(&format!("\n  {} = {} ({})", key, value, source));
+        }
+        report
+    }
+}
+
+/// Tells when the config file was saved again, by its modification time
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            modified: modified(path),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// True once after each change. A missing file is no change, editors may
+    /// replace the file by deleting it first.
+    pub fn changed(&mut self) -> bool {
+        match modified(&self.path) {
+            Some(time) if Some(time) != self.modified => {
+                self.modified = Some(time);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reads a whole number of `[default]`, the error names the key and the value.
+/// Negative numbers are errors, they must not become huge ones.
+fn read_uint<T: FromStr + fmt::Display>(config_file: &Ini, key: &str, max: T) -> Result<Option<T>, String> {
+    match config_file.get("default", key) {
+        Some(value) if !value.trim().is_empty() => match value.trim().parse() {
+            Ok(n) => Ok(Some(n)),
+            Err(_) => Err(format!("{} = {} ignored, it must be a number from 0 to {}", key, value.trim(), max)),
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Reads the KOMSI codes a port receives, all codes if the key is missing or wrong.
+fn read_filter(
+    config_file: &Ini,
+    section: &str,
+    key: &str,
+    problems: &mut Vec<Problem>,
+) -> CommandFilter {
+    match config_file.get(section, key) {
+        Some(value) if !value.is_empty() => CommandFilter::parse(&value).unwrap_or_else(|e| {
+            problems.push(Problem::key(
+                section,
+                key,
+                format!("{} ignored, the port receives all commands: {}", key, e),
+            ));
+            CommandFilter::all()
+        }),
+        _ => CommandFilter::all(),
+    }
+}
@@ -0,0 +1,291 @@
+// KOMSI console for firmware development (--console): typed commands like `A1 y57` are sent
+// to one port as a frame, what the device sends back is shown decoded.
+//
+// :wait 500     pause in ms, useful in scripts
+// :run FILE     sends the lines of a file, same syntax, # starts a comment
+// :history      numbered list of the sent lines, !3 sends line 3 again
+// :help, :quit
+
+use std::fmt;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use komsi::KomsiCommand;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::sink::KomsiSink;
+use crate::values::split_frame;
+
+/// How often the port is checked for received commands
+const RECEIVE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long the console waits for the port before the first line
+const OPEN_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub const CONSOLE_HELP: &str = "\
+A1 y57 D2     KOMSI commands, sent as one frame
+:wait 500     pause in ms
+:run FILE     send the lines of a file, # starts a comment
+:history      the sent lines, !3 sends line 3 again
+:help         this help
+:quit         end, also Ctrl-C";
+
+/// A line typed in the console or read from a script
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConsoleLine {
+    Empty,
+    Send(Vec<KomsiCommand>),
+    Wait(Duration),
+    Run(PathBuf),
+    History,
+    /// number of the history entry, starting at 1
+    Repeat(usize),
+    Help,
+    Quit,
+}
+
+impl ConsoleLine {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(ConsoleLine::Empty);
+        }
+
+        if let Some(number) = line.strip_prefix('!') {
+            return match number.trim().parse() {
+                Ok(n) if n > 0 => Ok(ConsoleLine::Repeat(n)),
+                _ => Err(format!("{} is no history entry, see :history", line)),
+            };
+        }
+
+        if let Some(meta) = line.strip_prefix(':') {
+            let (command, argument) = match meta.split_once(char::is_whitespace) {
+                Some((command, argument)) => (command, argument.trim()),
+                None => (meta, ""),
+            };
+            return match (command, argument) {
+                ("wait", ms) => ms
+                    .parse()
+                    .map(|ms| ConsoleLine::Wait(Duration::from_millis(ms)))
+                    .map_err(|_| format!(":wait needs the time in ms, not {:?}", ms)),
+                ("run", "") => Err(":run needs a file".to_string()),
+                ("run", file) => Ok(ConsoleLine::Run(PathBuf::from(file))),
+                ("history", "") => Ok(ConsoleLine::History),
+                ("help", "") => Ok(ConsoleLine::Help),
+                ("quit", "") => Ok(ConsoleLine::Quit),
+                _ => Err(format!("unknown command {}, see :help", line)),
+            };
+        }
+
+        if let Some(c) = line
+            .chars()
+            .find(|c| !c.is_ascii_alphanumeric() && !c.is_whitespace())
+        {
+            return Err(format!("{} is not part of a KOMSI command", c));
+        }
+
+        let mut commands = Vec::new();
+        for (code, digits) in split_frame(line.as_bytes()) {
+            let command = format!("{}{}", code, digits);
+            // without digits the device would get 0
+            if digits.is_empty() {
+                return Err(format!("{} has no value", code));
+            }
+            commands.push(
+                command
+                    .parse()
+                    .map_err(|_| format!("{} is not a KOMSI command", command))?,
+            );
+        }
+        Ok(ConsoleLine::Send(commands))
+    }
+}
+
+/// The lines of a script, an error tells the line
+pub fn read_script(path: &Path) -> Result<Vec<ConsoleLine>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+
+    let mut lines = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = ConsoleLine::parse(line)
+            .map_err(|e| format!("{}:{}: {}", path.display(), n + 1, e))?;
+        match line {
+            ConsoleLine::Send(_) | ConsoleLine::Wait(_) => lines.push(line),
+            ConsoleLine::Empty => {}
+            _ => {
+                return Err(format!(
+                    "{}:{}: only KOMSI commands and :wait can be used in a script",
+                    path.display(),
+                    n + 1
+                ));
+            }
+        }
+    }
+    Ok(lines)
+}
+
+/// The frame of the commands, as the bridge sends it
+pub fn console_frame(commands: &[KomsiCommand]) -> Vec<u8> {
+    let mut frame = Vec::new();
+    for cmd in commands {
+        frame.extend_from_slice(&KomsiCommand::build(cmd));
+    }
+    frame.extend_from_slice(&KomsiCommand::build_eol());
+    frame
+}
+
+/// `H1 FrontDoor(true)`, the code as sent and what it means
+pub struct Decoded<'a>(pub &'a KomsiCommand);
+
+impl fmt::Display for Decoded<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = KomsiCommand::build(self.0);
+        write!(f, "{} {:?}", String::from_utf8_lossy(&code), self.0)
+    }
+}
+
+pub struct Console {
+    sink: Box<dyn KomsiSink>,
+    /// sent lines, for :history and !n
+    history: Vec<Vec<KomsiCommand>>,
+    shutdown: CancellationToken,
+}
+
+impl Console {
+    pub fn new(sink: Box<dyn KomsiSink>, shutdown: CancellationToken) -> Self {
+        Self {
+            sink,
+            history: Vec::new(),
+            shutdown,
+        }
+    }
+
+    /// Reads stdin until :quit, its end or Ctrl-C, then gives the port back to be closed.
+    pub async fn run(mut self) -> Box<dyn KomsiSink> {
+        // stdin blocks, so it is read in a thread
+        let (lines, mut typed) = mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if lines.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        println!("KOMSI console on {}", self.sink.name());
+        println!("{}", CONSOLE_HELP);
+
+        // the port is opened in the background, piped lines would come too early
+        let deadline = tokio::time::Instant::now() + OPEN_TIMEOUT;
+        while !self.sink.connected()
+            && tokio::time::Instant::now() < deadline
+            && !self.shutdown.is_cancelled()
+        {
+            tokio::time::sleep(RECEIVE_INTERVAL).await;
+        }
+
+        let mut receive = tokio::time::interval(RECEIVE_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                _ = receive.tick() => self.show_received(),
+                line = typed.recv() => {
+                    let Some(line) = line else {
+                        break;
+                    };
+                    match ConsoleLine::parse(&line) {
+                        Ok(ConsoleLine::Quit) => break,
+                        Ok(line) => self.execute(line).await,
+                        Err(e) => println!("{}", e),
+                    }
+                }
+            }
+        }
+
+        self.show_received();
+        self.sink
+    }
+
+    async fn execute(&mut self, line: ConsoleLine) {
+        match line {
+            ConsoleLine::Empty | ConsoleLine::Quit => {}
+            ConsoleLine::Send(commands) => {
+                self.send(&commands);
+                self.history.push(commands);
+            }
+            ConsoleLine::Wait(duration) => self.wait(duration).await,
+            ConsoleLine::Run(path) => match read_script(&path) {
+                Ok(lines) => {
+                    for line in lines {
+                        if self.shutdown.is_cancelled() {
+                            break;
+                        }
+                        match line {
+                            ConsoleLine::Send(commands) => self.send(&commands),
+                            ConsoleLine::Wait(duration) => self.wait(duration).await,
+                            _ => {}
+                        }
+                    }
+                }
+                Err(e) => println!("{}", e),
+            },
+            ConsoleLine::History => {
+                for (n, commands) in self.history.iter().enumerate() {
+                    let line: Vec<String> = commands
+                        .iter()
+                        .map(|cmd| String::from_utf8_lossy(&KomsiCommand::build(cmd)).into_owned())
+                        .collect();
+                    println!("{:>4}  {}", n + 1, line.join(" "));
+                }
+            }
+            ConsoleLine::Repeat(n) => match self.history.get(n - 1).cloned() {
+                Some(commands) => {
+                    self.send(&commands);
+                    self.history.push(commands);
+                }
+                None => println!("!{} is no history entry, see :history", n),
+            },
+            ConsoleLine::Help => println!("{}", CONSOLE_HELP),
+        }
+    }
+
+    fn send(&mut self, commands: &[KomsiCommand]) {
+        match self.sink.send(&console_frame(commands)) {
+            Ok(()) => {
+                for cmd in commands {
+                    println!("-> {}", Decoded(cmd));
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotConnected => {
+                println!("{} is not open, nothing sent.", self.sink.name())
+            }
+            Err(e) => println!("Error writing to port {}: {}", self.sink.name(), e),
+        }
+    }
+
+    /// Received commands are still shown while waiting
+    async fn wait(&mut self, duration: Duration) {
+        let end = tokio::time::Instant::now() + duration;
+        let mut receive = tokio::time::interval(RECEIVE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => return,
+                _ = tokio::time::sleep_until(end) => return,
+                _ = receive.tick() => self.show_received(),
+            }
+        }
+    }
+
+    fn show_received(&mut self) {
+        for cmd in self.sink.receive() {
+            println!("<- {}", Decoded(&cmd));
+        }
+    }
+}
@@ -0,0 +1,149 @@
+// What can go wrong when reading the telemetry API, and how long to wait before trying again.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::schedule::Backoff;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TelemetryError {
+    /// Nobody listens on the port: TheBus is not running or its telemetry API is off
+    ConnectionRefused(String),
+    /// TheBus did not answer in time, e.g. while loading a map
+    Timeout(String),
+    /// TheBus answered with an error status, the path of the request
+    HttpStatus(u16, String),
+    /// The JSON does not fit what we expect, e.g. after an update of TheBus
+    Schema(String),
+    /// The vehicle is not there anymore, e.g. the player took another bus
+    VehicleNotFound(String),
+    /// Everything else, e.g. errors read from a recording
+    Other(String),
+}
+
+impl TelemetryError {
+    /// TheBus does not run, the devices are told so
+    pub fn is_not_running(&self) -> bool {
+        matches!(self, TelemetryError::ConnectionRefused(_))
+    }
+
+    /// Short name of the kind, errors of the same kind share their backoff
+    pub fn kind(&self) -> String {
+        match self {
+            TelemetryError::ConnectionRefused(_) => "connection_refused".to_string(),
+            TelemetryError::Timeout(_) => "timeout".to_string(),
+            TelemetryError::HttpStatus(status, _) => format!("http_{}", status),
+            TelemetryError::Schema(_) => "schema".to_string(),
+            TelemetryError::VehicleNotFound(_) => "vehicle_not_found".to_string(),
+            TelemetryError::Other(_) => "other".to_string(),
+        }
+    }
+
+    fn detail(&self) -> &str {
+        match self {
+            TelemetryError::ConnectionRefused(d)
+            | TelemetryError::Timeout(d)
+            | TelemetryError::HttpStatus(_, d)
+            | TelemetryError::Schema(d)
+            | TelemetryError::VehicleNotFound(d)
+            | TelemetryError::Other(d) => d,
+        }
+    }
+
+    /// The waits before the next tries, based on the error interval of the bridge:
+    /// - TheBus not running: long, it will not start within a second
+    /// - vehicle not found: none, the vehicle name is read again at once
+    /// - schema: long, it does not fix itself and every try logs the error
+    /// - timeout, HTTP status and the rest: normal
+    pub fn backoff(&self, error_interval: Duration, max_error_interval: Duration) -> Backoff {
+        match self {
+            TelemetryError::ConnectionRefused(_) => {
+                Backoff::new(error_interval, max_error_interval * 3)
+            }
+            TelemetryError::VehicleNotFound(_) => Backoff::new(Duration::ZERO, error_interval),
+            TelemetryError::Schema(_) => {
+                Backoff::new(error_interval * 2, max_error_interval * 6)
+            }
+            TelemetryError::Timeout(_)
+            | TelemetryError::HttpStatus(..)
+            | TelemetryError::Other(_) => Backoff::new(error_interval, max_error_interval),
+        }
+    }
+
+    /// Classifies a failed HTTP request.
+    pub fn from_reqwest(e: reqwest::Error, path: &str) -> Self {
+        let detail = format!("{}: {}", path, error_chain(&e));
+        if e.is_timeout() {
+            TelemetryError::Timeout(detail)
+        } else if e.is_connect() {
+            TelemetryError::ConnectionRefused(detail)
+        } else if e.is_decode() {
+            TelemetryError::Schema(detail)
+        } else if let Some(status) = e.status() {
+            TelemetryError::HttpStatus(status.as_u16(), path.to_string())
+        } else {
+            TelemetryError::Other(detail)
+        }
+    }
+
+    /// `kind: detail`, as written to recordings
+    pub fn to_record(&self) -> String {
+        format!("{}: {}", self.kind(), self.detail())
+    }
+
+    /// Reads `to_record` back, anything else becomes `Other`.
+    pub fn from_record(s: &str) -> Self {
+        let Some((kind, detail)) = s.split_once(": ") else {
+            return TelemetryError::Other(s.to_string());
+        };
+        let detail = detail.to_string();
+
+        match kind {
+            "connection_refused" => TelemetryError::ConnectionRefused(detail),
+            "timeout" => TelemetryError::Timeout(detail),
+            "schema" => TelemetryError::Schema(detail),
+            "vehicle_not_found" => TelemetryError::VehicleNotFound(detail),
+            "other" => TelemetryError::Other(detail),
+            _ => match kind.strip_prefix("http_").and_then(|s| s.parse().ok()) {
+                Some(status) => TelemetryError::HttpStatus(status, detail),
+                None => TelemetryError::Other(s.to_string()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for TelemetryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TelemetryError::ConnectionRefused(d) => {
+                write!(f, "TheBus is not reachable, is it running? ({})", d)
+            }
+            TelemetryError::Timeout(d) => write!(f, "TheBus did not answer in time ({})", d),
+            TelemetryError::HttpStatus(status, path) => {
+                write!(f, "TheBus answered {} with HTTP status {}", path, status)
+            }
+            TelemetryError::Schema(d) => write!(f, "unexpected JSON ({})", d),
+            TelemetryError::VehicleNotFound(name) => write!(f, "vehicle {} not found", name),
+            TelemetryError::Other(d) => write!(f, "{}", d),
+        }
+    }
+}
+
+impl std::error::Error for TelemetryError {}
+
+impl From<serde_json::Error> for TelemetryError {
+    fn from(e: serde_json::Error) -> Self {
+        TelemetryError::Schema(e.to_string())
+    }
+}
+
+/// reqwest hides the interesting part (e.g. "Connection refused") in its sources
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(s) = source {
+        message = format!("{}: {}", message, s);
+        source = s.source();
+    }
+    message
+}
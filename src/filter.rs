@@ -0,0 +1,172 @@
+// Routing of KOMSI commands to single ports.
+//
+// In a rig with several boards every board only needs some of the commands,
+// e.g. one drives the gauges, another one the lamp panel:
+//
+// [default]
+// portname = com8
+// filter = s, x, y
+// portname2 = com9
+// filter2 = A-N
+//
+// `filter` belongs to `portname`, `filter2` to `portname2`, ... and `usbfilter` to `usb`, ...
+// Ports without filter receive all commands.
+
+use std::io;
+
+use komsi::KomsiCommand;
+use tokio::task::JoinHandle;
+
+use crate::sink::KomsiSink;
+
+/// SimulatorType is always sent, the boards need it to start
+const ALWAYS_SENT: char = 'O';
+
+/// The KOMSI codes a port receives
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandFilter {
+    /// None: all codes
+    codes: Option<Vec<char>>,
+}
+
+impl CommandFilter {
+    /// A filter which lets everything pass
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Parses a list of KOMSI codes, separated by commas or spaces.
+    /// Ranges like `A-M` are allowed, as are several codes in one word like `sxy`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut codes = Vec::new();
+
+        for word in s.split(|c: char| c == ',' || c.is_whitespace()) {
+            let chars: Vec<char> = word.chars().collect();
+            match chars.as_slice() {
+                [] => {}
+                [from, '-', to] => {
+                    if from > to {
+                        return Err(format!("{} is not a valid range", word));
+                    }
+                    codes.extend((*from..=*to).filter(|c| c.is_ascii_alphabetic()));
+                }
+                _ => codes.extend(chars),
+            }
+        }
+
+        for &code in &codes {
+            // DateTime has no value which can be checked with 0
+            if code != 'r' && KomsiCommand::from_parts(code, b"0").is_err() {
+                return Err(format!("{} is not a KOMSI code", code));
+            }
+        }
+
+        if codes.is_empty() {
+            return Err("no KOMSI codes".to_string());
+        }
+
+        codes.sort();
+        codes.dedup();
+        Ok(Self { codes: Some(codes) })
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.codes.is_none()
+    }
+
+    pub fn allows(&self, code: char) -> bool {
+        match &self.codes {
+            None => true,
+            Some(codes) => code == ALWAYS_SENT || codes.contains(&code),
+        }
+    }
+
+    /// Removes the commands the port does not receive from a frame.
+    /// Returns an empty frame if nothing is left.
+    pub fn apply(&self, frame: &[u8]) -> Vec<u8> {
+        if self.is_all() {
+            return frame.to_vec();
+        }
+
+        let eol = KomsiCommand::build_eol();
+        let mut filtered = Vec::new();
+        let mut keep = false;
+
+        for &b in frame.strip_suffix(eol.as_slice()).unwrap_or(frame) {
+            // every command starts with its code and is followed by digits
+            if b.is_ascii_alphabetic() {
+                keep = self.allows(b as char);
+            }
+            if keep {
+                filtered.push(b);
+            }
+        }
+
+        if !filtered.is_empty() {
+            filtered.extend_from_slice(&eol);
+        }
+        filtered
+    }
+}
+
+/// Sends only the commands of the filter to the inner sink.
+pub struct FilteredSink {
+    inner: Box<dyn KomsiSink>,
+    filter: CommandFilter,
+}
+
+impl FilteredSink {
+    pub fn new(inner: Box<dyn KomsiSink>, filter: CommandFilter) -> Self {
+        Self { inner, filter }
+    }
+}
+
+impl KomsiSink for FilteredSink {
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        let frame = self.filter.apply(frame);
+        if frame.is_empty() {
+            return Ok(());
+        }
+        self.inner.send(&frame)
+    }
+
+    fn send_full_state(&mut self, frame: &[u8]) -> io::Result<()> {
+        let frame = self.filter.apply(frame);
+        if frame.is_empty() {
+            return Ok(());
+        }
+        self.inner.send_full_state(&frame)
+    }
+
+    fn receive(&mut self) -> Vec<KomsiCommand> {
+        self.inner.receive()
+    }
+
+    fn reconnected(&mut self) -> bool {
+        self.inner.reconnected()
+    }
+
+    fn connected(&self) -> bool {
+        self.inner.connected()
+    }
+
+    fn vehicle_changed(&mut self, vehicle_name: &str) {
+        self.inner.vehicle_changed(vehicle_name)
+    }
+
+    fn receive_actions(&mut self) -> Vec<String> {
+        self.inner.receive_actions()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.inner.close()
+    }
+
+    fn closing(&mut self) -> Option<JoinHandle<()>> {
+        self.inner.closing()
+    }
+}
@@ -0,0 +1,162 @@
+// Input direction of the KOMSI protocol:
+// the dashboard hardware sends KOMSI commands (e.g. "H1\n" when a door button is pressed)
+// and we turn them into button actions of TheBus telemetry API.
+
+use configparser::ini::Ini;
+use komsi::KomsiCommand;
+
+use crate::config::Problem;
+
+/// Name of the config section with the KOMSI command to TheBus action table
+pub const INPUT_SECTION: &str = "input";
+
+/// Longest line which is decoded, a KOMSI line is much shorter. Without an end of line
+/// (wrong baudrate, no KOMSI device) the bytes are dropped instead of collected forever.
+pub const MAX_LINE: usize = 256;
+
+/// Splits the incoming byte stream of a port into KOMSI commands.
+///
+/// Commands are collected until the end of line (`\n`) is received,
+/// like the output direction a line can contain several commands, e.g. `A1H1\n`.
+#[derive(Debug, Default)]
+pub struct KomsiDecoder {
+    line: Vec<u8>,
+    /// the line got too long, the rest up to the end of line is dropped
+    overlong: bool,
+}
+
+impl KomsiDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds received bytes into the decoder and returns all commands of completed lines.
+    /// Tokens that are not valid KOMSI commands are skipped.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<KomsiCommand> {
+        let mut commands = Vec::new();
+
+        for &b in bytes {
+            match b {
+                b'\n' => {
+                    if !self.overlong {
+                        commands.extend(decode_line(&self.line));
+                    }
+                    self.line.clear();
+                    self.overlong = false;
+                }
+                b'\r' => {}
+                _ if self.overlong => {}
+                _ if self.line.len() >= MAX_LINE => {
+                    self.line.clear();
+                    self.overlong = true;
+                }
+                _ => self.line.push(b),
+            }
+        }
+
+        commands
+    }
+}
+
+/// Decodes one line (without end of line) into KOMSI commands.
+/// Every command starts with a letter and is followed by its digits.
+pub fn decode_line(line: &[u8]) -> Vec<KomsiCommand> {
+    let mut commands = Vec::new();
+    let mut i = 0;
+
+    while i < line.len() {
+        let cmd_char = line[i];
+        i += 1;
+
+        if !cmd_char.is_ascii_alphabetic() {
+            continue;
+        }
+
+        let start = i;
+        while i < line.len() && line[i].is_ascii_digit() {
+            i += 1;
+        }
+
+        if let Ok(cmd) = KomsiCommand::from_parts(cmd_char as char, &line[start..i]) {
+            commands.push(cmd);
+        }
+    }
+
+    commands
+}
+
+/// Mapping of KOMSI commands received from the dashboard to TheBus button actions.
+///
+/// Configured in the `[input]` section of TheBus2Komsi.ini, the key is the KOMSI command
+/// the hardware sends, the value is the action name as listed in `Buttons.Actions` of the vehicle:
+///
+/// ```ini
+/// [input]
+/// H1 = DoorFrontOpen
+/// L1 = BusStopBrake
+/// ```
+#[derive(Debug, Default)]
+pub struct InputMap {
+    entries: Vec<(KomsiCommand, String)>,
+}
+
+impl InputMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the `[input]` section of the config file.
+    /// The ini must be case sensitive because KOMSI uses e.g. `D` (Indicator) and `d` (DebugMode).
+    /// Entries which cannot be used are ignored and added to `problems`.
+    pub fn from_ini(ini: &Ini, verbose: bool, problems: &mut Vec<Problem>) -> Self {
+        let mut map = Self::new();
+
+        let Some(section) = ini.get_map_ref().get(INPUT_SECTION) else {
+            return map;
+        };
+
+        for (key, value) in section {
+            let action = value.as_deref().unwrap_or("").trim();
+            if action.is_empty() {
+                problems.push(Problem::key(
+                    INPUT_SECTION,
+                    key,
+                    format!("Input mapping {} has no action, ignored.", key),
+                ));
+                continue;
+            }
+
+            match key.parse::<KomsiCommand>() {
+                Ok(cmd) => {
+                    if verbose {
+                        println!("Input mapping: {} -> {}", key, action);
+                    }
+                    map.insert(cmd, action.to_string());
+                }
+                Err(e) => problems.push(Problem::key(
+                    INPUT_SECTION,
+                    key,
+                    format!("Input mapping {} is not a KOMSI command: {:?}", key, e),
+                )),
+            }
+        }
+
+        map
+    }
+
+    pub fn insert(&mut self, cmd: KomsiCommand, action: String) {
+        self.entries.push((cmd, action));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the action for the received command, if there is one.
+    pub fn action(&self, cmd: &KomsiCommand) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(c, _)| c == cmd)
+            .map(|(_, action)| action.as_str())
+    }
+}
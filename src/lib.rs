@@ -1,4 +1,24 @@
 // This file exposes the modules used by both binary targets and integration tests
+pub mod bridge;
+pub mod check;
+pub mod config;
+pub mod console;
+pub mod error;
+pub mod filter;
+pub mod input;
+pub mod mapping;
+pub mod mockapi;
+pub mod mqtt;
+pub mod network;
 pub mod opts;
+pub mod pattern;
+pub mod profile;
+pub mod record;
 pub mod serial;
 pub mod realmain;
+pub mod schedule;
+pub mod setup;
+pub mod sink;
+pub mod source;
+pub mod values;
+pub mod web;
@@ -0,0 +1,309 @@
+// User defined mapping of API values to KOMSI codes.
+//
+// The built-in mapping (see API-Mapping.md) is done by the_bus_telemetry::get_vehicle_state_from_api,
+// the entries of the [mapping] section are applied on top of it:
+//
+// [mapping]
+// t = RPM, round
+// v = EngineTemperature, round
+// x = DisplayFuel, scale 100
+// G = AllLamps.LightHeadlight, threshold 0.5
+
+use std::collections::BTreeMap;
+
+use configparser::ini::Ini;
+use komsi::vehicle::{VehicleLogger, VehicleState};
+use komsi::KomsiCommand;
+use serde_json::Value;
+
+use crate::config::Problem;
+
+/// Name of the config section with the user defined mapping
+pub const MAPPING_SECTION: &str = "mapping";
+
+/// KOMSI values which are not part of `VehicleState`, keyed by KOMSI code
+pub type ExtraValues = BTreeMap<char, u64>;
+
+/// Conversion of the JSON value into the KOMSI value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Conversion {
+    /// 0 -> 0, everything else -> 1
+    Bool,
+    /// round of value
+    Round,
+    /// abs of round of value
+    Abs,
+    /// round of (factor*value)
+    Scale(f64),
+    /// value > limit -> 1, else 0
+    Threshold(f64),
+}
+
+impl Conversion {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut parts = s.split_whitespace();
+        let name = parts.next().unwrap_or("round").to_lowercase();
+        let arg = parts.next();
+
+        let number = |arg: Option<&str>| -> Result<f64, String> {
+            arg.ok_or_else(|| format!("{} needs a number", name))?
+                .parse::<f64>()
+                .map_err(|_| format!("{} needs a number", name))
+        };
+
+        let conversion = match name.as_str() {
+            "bool" => Conversion::Bool,
+            "round" => Conversion::Round,
+            "abs" => Conversion::Abs,
+            "scale" => Conversion::Scale(number(arg)?),
+            "threshold" => Conversion::Threshold(number(arg)?),
+            _ => return Err(format!("unknown conversion {}", name)),
+        };
+
+        if parts.next().is_some() {
+            return Err(format!("too many arguments for {}", name));
+        }
+
+        Ok(conversion)
+    }
+
+    pub fn apply(&self, value: f64) -> u64 {
+        let v = match self {
+            Conversion::Bool => (value != 0.0) as u8 as f64,
+            Conversion::Round => value.round(),
+            Conversion::Abs => value.round().abs(),
+            Conversion::Scale(factor) => (value * factor).round(),
+            Conversion::Threshold(limit) => (value > *limit) as u8 as f64,
+        };
+
+        // KOMSI only knows positive values
+        if v > 0.0 { v as u64 } else { 0 }
+    }
+}
+
+/// One line of the [mapping] section
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappingEntry {
+    pub code: char,
+    pub path: String,
+    pub conversion: Conversion,
+}
+
+impl MappingEntry {
+    /// Parses `<code> = <json path>[, <conversion>]`
+    pub fn parse(code: &str, value: &str) -> Result<Self, String> {
+        let mut chars = code.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => c,
+            _ => return Err(format!("{} is not a single KOMSI code", code)),
+        };
+
+        // check if the code is known, the value does not matter here
+        KomsiCommand::from_parts(code, b"0")
+            .map_err(|_| format!("{} is not a KOMSI code", code))?;
+
+        if code == 'r' {
+            return Err("r (DateTime) cannot be mapped".to_string());
+        }
+
+        let (path, conversion) = match value.split_once(',') {
+            Some((path, conversion)) => (path.trim(), Conversion::parse(conversion.trim())?),
+            None => (value.trim(), Conversion::Round),
+        };
+
+        if path.is_empty() {
+            return Err(format!("{} has no JSON path", code));
+        }
+
+        Ok(Self {
+            code,
+            path: path.to_string(),
+            conversion,
+        })
+    }
+
+    /// Reads the value from the vehicle JSON, None if the path does not exist
+    pub fn value(&self, vehicle: &Value) -> Option<u64> {
+        lookup(vehicle, &self.path)
+            .and_then(as_number)
+            .map(|v| self.conversion.apply(v))
+    }
+}
+
+/// All entries of the [mapping] section
+#[derive(Debug, Default, Clone)]
+pub struct Mapping {
+    pub entries: Vec<MappingEntry>,
+}
+
+impl Mapping {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the `[mapping]` section of the (case sensitive) config file.
+    /// Entries which cannot be used are ignored and added to `problems`.
+    pub fn from_ini(ini: &Ini, verbose: bool, problems: &mut Vec<Problem>) -> Self {
+        let mut mapping = Self::new();
+
+        if let Some(section) = ini.get_map_ref().get(MAPPING_SECTION) {
+            mapping.read_section(section, verbose, problems);
+        }
+
+        mapping
+    }
+
+    /// Adds the entries of a config section, entries for the same code are replaced.
+    pub fn read_section(
+        &mut self,
+        section: &std::collections::HashMap<String, Option<String>>,
+        verbose: bool,
+        problems: &mut Vec<Problem>,
+    ) {
+        for (key, value) in section {
+            match MappingEntry::parse(key, value.as_deref().unwrap_or("")) {
+                Ok(entry) => {
+                    if verbose {
+                        println!(
+                            "Mapping: {} -> {} ({:?})",
+                            entry.path, entry.code, entry.conversion
+                        );
+                    }
+                    self.insert(entry);
+                }
+                Err(e) => problems.push(Problem::key(
+                    MAPPING_SECTION,
+                    key,
+                    format!("Mapping {} ignored: {}", key, e),
+                )),
+            }
+        }
+    }
+
+    pub fn insert(&mut self, entry: MappingEntry) {
+        self.entries.retain(|e| e.code != entry.code);
+        self.entries.push(entry);
+        self.entries.sort_by_key(|e| e.code);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Applies the mapping to the vehicle JSON.
+    /// Codes which are part of `VehicleState` overwrite the built-in value,
+    /// all other codes are returned as extra values.
+    pub fn apply(&self, vehicle: &Value, state: &mut VehicleState) -> ExtraValues {
+        let mut extra = ExtraValues::new();
+
+        for entry in &self.entries {
+            if let Some(value) = entry.value(vehicle)
+                && !set_state_value(state, entry.code, value)
+            {
+                extra.insert(entry.code, value);
+            }
+        }
+
+        extra
+    }
+}
+
+/// Looks up a value by a path like `AllLamps.ButtonLight Door 1`,
+/// numbers select an array entry, e.g. `Wheels.0.OnGround`.
+pub fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |v, key| match v {
+        Value::Object(map) => map.get(key),
+        Value::Array(list) => key.parse::<usize>().ok().and_then(|i| list.get(i)),
+        _ => None,
+    })
+}
+
+/// TheBus sends booleans as strings "true"/"false"
+pub fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::Bool(b) => Some(*b as u8 as f64),
+        Value::String(s) => match s.as_str() {
+            "true" => Some(1.0),
+            "false" => Some(0.0),
+            _ => s.trim().parse().ok(),
+        },
+        _ => None,
+    }
+}
+
+/// Sets the field of the state belonging to the KOMSI code.
+/// Returns false if the code is not part of `VehicleState`.
+pub fn set_state_value(state: &mut VehicleState, code: char, value: u64) -> bool {
+    let on = value != 0;
+    match code {
+        'A' => state.ignition = on,
+        'B' => state.engine = on,
+        'C' => state.doors = on,
+        'D' => state.indicator = value.min(u8::MAX as u64) as u8,
+        'E' => state.fixing_brake = on,
+        'F' => state.lights_warning = on,
+        'G' => state.lights_main = on,
+        'H' => state.lights_front_door = on,
+        'I' => state.lights_second_door = on,
+        'J' => state.lights_third_door = on,
+        'K' => state.lights_stop_request = on,
+        'L' => state.lights_stop_brake = on,
+        'M' => state.lights_high_beam = on,
+        'N' => state.battery_light = on,
+        'P' => state.door_clearance = on,
+        'o' => {
+            state.total_distance = value;
+            state.total_distance_km = value / 1000;
+        }
+        's' => state.maxspeed = value.min(u32::MAX as u64) as u32,
+        'x' => state.fuel = value.min(u8::MAX as u64) as u8,
+        'y' => state.speed = value.min(u32::MAX as u64) as u32,
+        _ => return false,
+    }
+    true
+}
+
+/// Like `VehicleState::compare`, but for the extra values.
+/// Returns the KOMSI commands without end of line.
+pub fn compare_extra(
+    old: &ExtraValues,
+    new: &ExtraValues,
+    force: bool,
+    logger: Option<&dyn VehicleLogger>,
+) -> Vec<u8> {
+    let mut buffer = Vec::new();
+
+    for (&code, &value) in new {
+        let old_value = old.get(&code).copied();
+        if old_value == Some(value) && !force {
+            continue;
+        }
+
+        if let Some(l) = logger {
+            l.log(format!("{}: {} -> {} ", code, old_value.unwrap_or(0), value));
+        }
+
+        if let Ok(cmd) = KomsiCommand::from_parts(code, value.to_string().as_bytes()) {
+            buffer.extend_from_slice(&cmd.build());
+        }
+    }
+
+    buffer
+}
+
+/// Appends KOMSI commands to a command buffer created by `VehicleState::compare`,
+/// the buffer keeps exactly one end of line at the end.
+pub fn append_commands(cmdbuf: &mut Vec<u8>, commands: &[u8]) {
+    if commands.is_empty() {
+        return;
+    }
+
+    let eol = KomsiCommand::build_eol();
+    if cmdbuf.ends_with(&eol) {
+        cmdbuf.truncate(cmdbuf.len() - eol.len());
+    }
+
+    cmdbuf.extend_from_slice(commands);
+    cmdbuf.extend_from_slice(&eol);
+}
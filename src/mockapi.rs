@@ -0,0 +1,368 @@
+// A mock of the telemetry API of TheBus, so the bridge can be run and tested without the game.
+//
+// Serves the endpoints used by TheBus2Komsi on a local port:
+// /player, /vehicles/current, /vehicles/<name>, /vehicles/<name>/<cmd> and /world.
+// The vehicles are served from fixture JSONs (see tests/json) or from a scenario
+// built on top of a fixture (ignition on, doors open, accelerate, indicator, ...).
+
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// Date and time of the world at start of the mock
+pub const MOCK_WORLD_TIME: &str = "2026-01-01T09:43:48";
+
+const DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// A vehicle of the mock, every request of the vehicle serves the next frame.
+/// The last frame is served again and again, unless the frames are looped.
+#[derive(Debug, Clone)]
+struct MockVehicle {
+    name: String,
+    frames: Vec<Value>,
+    requests: u64,
+}
+
+#[derive(Debug)]
+struct MockState {
+    vehicles: Vec<MockVehicle>,
+    /// Name of the vehicle the player sits in
+    player: Option<String>,
+    world_time: NaiveDateTime,
+    /// Every frame is served for this number of requests
+    requests_per_frame: u64,
+    looping: bool,
+    /// Commands sent to the vehicles, e.g. `sendeventpress?event=DoorFrontOpen`
+    commands: Vec<String>,
+}
+
+/// State of the mock API, shared between the server and the test (or the binary).
+#[derive(Debug, Clone)]
+pub struct MockApi {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl Default for MockApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockApi {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MockState {
+                vehicles: Vec::new(),
+                player: None,
+                world_time: NaiveDateTime::parse_from_str(MOCK_WORLD_TIME, DATETIME_FORMAT)
+                    .unwrap(),
+                requests_per_frame: 1,
+                looping: false,
+                commands: Vec::new(),
+            })),
+        }
+    }
+
+    /// Adds all `*.json` files of a directory as vehicles.
+    pub fn from_dir(dir: &Path) -> io::Result<Self> {
+        let api = Self::new();
+
+        let mut paths: Vec<_> = fs::read_dir(dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == "json"))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            api.add_vehicle(read_fixture(&path)?);
+        }
+
+        Ok(api)
+    }
+
+    /// Adds a vehicle with a single frame, the name is taken from `ActorName`.
+    pub fn add_vehicle(&self, vehicle: Value) {
+        self.add_frames(vec![vehicle]);
+    }
+
+    /// Adds a vehicle with several frames, e.g. a scenario.
+    /// The name is taken from `ActorName` of the first frame.
+    pub fn add_frames(&self, frames: Vec<Value>) {
+        let Some(name) = frames
+            .first()
+            .and_then(|f| f.get("ActorName"))
+            .and_then(Value::as_str)
+        else {
+            eprintln!("Mock vehicle without ActorName ignored.");
+            return;
+        };
+
+        let vehicle = MockVehicle {
+            name: name.to_string(),
+            frames,
+            requests: 0,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.vehicles.retain(|v| !v.name.eq_ignore_ascii_case(&vehicle.name));
+        state.vehicles.push(vehicle);
+    }
+
+    /// Names of all vehicles
+    pub fn vehicle_names(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state.vehicles.iter().map(|v| v.name.clone()).collect()
+    }
+
+    /// First frame of the vehicle
+    pub fn vehicle(&self, name: &str) -> Option<Value> {
+        let state = self.state.lock().unwrap();
+        state
+            .vehicles
+            .iter()
+            .find(|v| v.name.eq_ignore_ascii_case(name))
+            .map(|v| v.frames[0].clone())
+    }
+
+    /// The player sits down in the vehicle, the vehicle starts again with its first frame.
+    pub fn enter(&self, name: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(v) = state
+            .vehicles
+            .iter_mut()
+            .find(|v| v.name.eq_ignore_ascii_case(name))
+        {
+            v.requests = 0;
+        }
+        state.player = Some(name.to_string());
+    }
+
+    /// The player leaves the vehicle.
+    pub fn leave(&self) {
+        self.state.lock().unwrap().player = None;
+    }
+
+    pub fn requests_per_frame(&self, requests: u64) {
+        self.state.lock().unwrap().requests_per_frame = requests.max(1);
+    }
+
+    /// Start again with the first frame after the last one.
+    pub fn looping(&self, looping: bool) {
+        self.state.lock().unwrap().looping = looping;
+    }
+
+    pub fn set_world_time(&self, time: NaiveDateTime) {
+        self.state.lock().unwrap().world_time = time;
+    }
+
+    /// Lets the time of the world pass.
+    pub fn advance_clock(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.world_time += chrono::Duration::from_std(duration).unwrap_or_default();
+    }
+
+    /// All commands sent to the vehicles so far
+    pub fn commands(&self) -> Vec<String> {
+        self.state.lock().unwrap().commands.clone()
+    }
+
+    /// Answers a GET request, returns the HTTP status and the JSON body.
+    /// Paths and vehicle names are case insensitive like in TheBus.
+    pub fn handle(&self, target: &str) -> (u16, Value) {
+        let mut state = self.state.lock().unwrap();
+
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+        let lower: Vec<String> = parts.iter().map(|p| p.to_lowercase()).collect();
+
+        match lower.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+            ["player"] => match &state.player {
+                Some(name) => (200, json!({ "Mode": "Vehicle", "CurrentVehicle": name })),
+                None => (200, json!({ "Mode": "Walking", "CurrentVehicle": "" })),
+            },
+            ["world"] => (
+                200,
+                json!({
+                    "LevelName": "Berlin",
+                    "DateTime": state.world_time.format(DATETIME_FORMAT).to_string(),
+                    "TimeFactor": 1.0,
+                    "BaseLatitude": 52.5,
+                    "BaseLongitude": 13.4
+                }),
+            ),
+            ["vehicles", _] => {
+                let name = match lower[1].as_str() {
+                    "current" => state.player.clone().unwrap_or_default(),
+                    _ => parts[1].to_string(),
+                };
+                match state.next_frame(&name) {
+                    Some(frame) => (200, frame),
+                    None => (404, json!({ "Error": format!("vehicle {} not found", name) })),
+                }
+            }
+            ["vehicles", _, _] => {
+                let command = match query {
+                    "" => parts[2].to_string(),
+                    _ => format!("{}?{}", parts[2], query),
+                };
+                state.commands.push(command);
+                (200, json!({ "Result": "Ok" }))
+            }
+            _ => (404, json!({ "Error": format!("{} not found", path) })),
+        }
+    }
+}
+
+impl MockState {
+    fn next_frame(&mut self, name: &str) -> Option<Value> {
+        let requests_per_frame = self.requests_per_frame;
+        let looping = self.looping;
+        let vehicle = self
+            .vehicles
+            .iter_mut()
+            .find(|v| v.name.eq_ignore_ascii_case(name))?;
+
+        let mut index = (vehicle.requests / requests_per_frame) as usize;
+        if looping {
+            index %= vehicle.frames.len();
+        } else {
+            index = index.min(vehicle.frames.len() - 1);
+        }
+        vehicle.requests += 1;
+
+        Some(vehicle.frames[index].clone())
+    }
+}
+
+/// Reads a vehicle JSON as delivered by `/vehicles/<name>`.
+pub fn read_fixture(path: &Path) -> io::Result<Value> {
+    let json = fs::read_to_string(path)?;
+    serde_json::from_str(&json).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })
+}
+
+/// A drive with the vehicle, one frame per step:
+/// parked, ignition on, engine on, doors open, doors closed, indicator left,
+/// accelerate to 50 km/h, indicator off, brake and stop.
+pub fn scenario(vehicle: &Value) -> Vec<Value> {
+    let steps = [
+        json!({ "IgnitionEnabled": "false", "EngineStarted": "false", "PassengerDoorsOpen": "false",
+                "IndicatorState": 0, "Speed": 0.0, "FixingBrake": "true" }),
+        json!({ "IgnitionEnabled": "true" }),
+        json!({ "EngineStarted": "true" }),
+        json!({ "PassengerDoorsOpen": "true" }),
+        json!({ "PassengerDoorsOpen": "false" }),
+        json!({ "IndicatorState": -1, "FixingBrake": "false" }),
+        json!({ "Speed": 10.0 }),
+        json!({ "Speed": 20.0, "IndicatorState": 0 }),
+        json!({ "Speed": 30.0 }),
+        json!({ "Speed": 40.0 }),
+        json!({ "Speed": 50.0 }),
+        json!({ "Speed": 30.0 }),
+        json!({ "Speed": 10.0 }),
+        json!({ "Speed": 0.0, "FixingBrake": "true" }),
+    ];
+
+    let mut frame = vehicle.clone();
+    steps
+        .iter()
+        .map(|changes| {
+            if let (Value::Object(frame), Value::Object(changes)) = (&mut frame, changes) {
+                for (key, value) in changes {
+                    frame.insert(key.clone(), value.clone());
+                }
+            }
+            frame.clone()
+        })
+        .collect()
+}
+
+/// The HTTP server of the mock, stopped when dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Starts the server, use port 0 to get a free port.
+    pub async fn start(api: MockApi, addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+
+        let task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(serve_connection(stream, api.clone()));
+                    }
+                    Err(e) => eprintln!("Mock API: error accepting connection: {}", e),
+                }
+            }
+        });
+
+        Ok(Self { addr, task })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// one request per connection is enough for the telemetry client
+async fn serve_connection(mut stream: TcpStream, api: MockApi) {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 16 * 1024 {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => request.extend_from_slice(&buffer[..n]),
+        }
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(target)) => api.handle(target),
+        (Some(_), Some(_)) => (405, json!({ "Error": "method not allowed" })),
+        _ => (400, json!({ "Error": "bad request" })),
+    };
+
+    let body = body.to_string();
+    let reason = match status {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Bad Request",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    );
+
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
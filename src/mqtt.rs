@@ -0,0 +1,316 @@
+// The vehicle state on an MQTT broker, for home cockpits with lights and motion rigs.
+//
+// [mqtt]
+// broker = 192.168.1.10:1883
+// topic = thebus
+//
+// Every KOMSI value is published retained to `thebus/<vehicle>/<name>`, e.g. `thebus/<vehicle>/speed`,
+// `thebus/vehicle` is the current vehicle and `thebus/status` is `online` or `offline` (last will).
+// A message to `thebus/<vehicle>/action` with an action name as payload (e.g. `DoorFrontOpen`)
+// is sent to TheBus like a button of the dashboard.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use configparser::ini::Ini;
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use tokio::task::JoinHandle;
+
+use crate::config::Problem;
+use crate::filter::CommandFilter;
+use crate::sink::KomsiSink;
+use crate::values::{code_name, json_value, KomsiValues};
+
+/// Name of the config section of the MQTT sink
+pub const MQTT_SECTION: &str = "mqtt";
+
+const DEFAULT_PORT: u16 = 1883;
+const DEFAULT_TOPIC: &str = "thebus";
+const DEFAULT_CLIENT_ID: &str = "TheBus2Komsi";
+
+/// A lost connection to the broker is tried again after this time
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Messages waiting for the broker, more are an error
+const QUEUE_CAPACITY: usize = 256;
+
+/// Settings of the `[mqtt]` section
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttConfig {
+    /// `host` or `host:port`
+    pub broker: String,
+    /// first level of all topics
+    pub topic: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub filter: CommandFilter,
+}
+
+impl MqttConfig {
+    pub fn new(broker: &str) -> Self {
+        Self {
+            broker: broker.to_string(),
+            topic: DEFAULT_TOPIC.to_string(),
+            client_id: DEFAULT_CLIENT_ID.to_string(),
+            username: None,
+            password: None,
+            filter: CommandFilter::all(),
+        }
+    }
+
+    /// Reads the `[mqtt]` section, None if there is no broker.
+    /// A filter which cannot be used is ignored and added to `problems`.
+    pub fn from_ini(ini: &Ini, problems: &mut Vec<Problem>) -> Option<Self> {
+        let get = |key: &str| ini.get(MQTT_SECTION, key).filter(|v| !v.is_empty());
+
+        let mut config = Self::new(&get("broker")?);
+        if let Some(topic) = get("topic") {
+            config.topic = topic.trim_end_matches('/').to_string();
+        }
+        if let Some(client_id) = get("clientid") {
+            config.client_id = client_id;
+        }
+        config.username = get("username");
+        config.password = get("password");
+        if let Some(filter) = get("filter") {
+            config.filter = CommandFilter::parse(&filter).unwrap_or_else(|e| {
+                problems.push(Problem::key(
+                    MQTT_SECTION,
+                    "filter",
+                    format!("mqtt filter ignored, all values are published: {}", e),
+                ));
+                CommandFilter::all()
+            });
+        }
+        Some(config)
+    }
+
+    /// Host and port of the broker
+    pub fn host_port(&self) -> Result<(String, u16), String> {
+        match self.broker.rsplit_once(':') {
+            Some((host, port)) => port
+                .parse()
+                .map(|port| (host.to_string(), port))
+                .map_err(|_| format!("{} is not a valid port", port)),
+            None => Ok((self.broker.clone(), DEFAULT_PORT)),
+        }
+    }
+}
+
+/// Vehicle names become one topic level, MQTT wildcards and separators are replaced.
+pub fn topic_level(name: &str) -> String {
+    name.replace(['/', '+', '#'], "_")
+}
+
+/// The payload of a KOMSI value: `true`/`false`, a number or the date time
+pub fn payload(code: char, digits: &str) -> String {
+    match json_value(code, digits) {
+        serde_json::Value::String(s) => s,
+        value => value.to_string(),
+    }
+}
+
+/// Publishes the KOMSI values to an MQTT broker and receives actions.
+///
+/// The connection runs in a task, it connects again when the broker was lost.
+pub struct MqttSink {
+    topic: String,
+    client: AsyncClient,
+    values: KomsiValues,
+    /// the current vehicle, the task only accepts actions for it
+    vehicle: Arc<Mutex<String>>,
+    actions: mpsc::Receiver<String>,
+    /// set by the task while the broker is connected
+    connected: Arc<AtomicBool>,
+    /// set by the task after (re)connecting, the broker may have lost the retained values
+    reconnected: Arc<AtomicBool>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl MqttSink {
+    /// Starts connecting to the broker, needs a tokio runtime.
+    pub fn connect(config: &MqttConfig, verbose: bool) -> io::Result<Self> {
+        let (host, port) = config
+            .host_port()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let status_topic = format!("{}/status", config.topic);
+        let mut options = MqttOptions::new(&config.client_id, host, port);
+        options.set_last_will(LastWill::new(&status_topic, "offline", QoS::AtLeastOnce, true));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.clone().unwrap_or_default());
+        }
+
+        let (client, mut eventloop) = AsyncClient::new(options, QUEUE_CAPACITY);
+        let (tx, actions) = mpsc::channel();
+        let vehicle = Arc::new(Mutex::new(String::new()));
+        let connected = Arc::new(AtomicBool::new(false));
+        let reconnected = Arc::new(AtomicBool::new(false));
+
+        let task_client = client.clone();
+        let task_vehicle = Arc::clone(&vehicle);
+        let task_connected = Arc::clone(&connected);
+        let task_reconnected = Arc::clone(&reconnected);
+        let topic = config.topic.clone();
+        let broker = config.broker.clone();
+        let task = tokio::spawn(async move {
+            let action_topic = format!("{}/+/action", topic);
+            let vehicle_topic = format!("{}/vehicle", topic);
+            // errors are reported once, not every second while the broker is away
+            let mut reported = false;
+
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        if verbose {
+                            println!("Connected to MQTT broker {}.", broker);
+                        }
+                        reported = false;
+                        let _ = task_client.try_publish(&status_topic, QoS::AtLeastOnce, true, "online");
+                        let _ = task_client.try_subscribe(&action_topic, QoS::AtMostOnce);
+                        // the vehicle may have changed while the broker was away
+                        let vehicle = task_vehicle.lock().unwrap().clone();
+                        let _ = task_client.try_publish(&vehicle_topic, QoS::AtMostOnce, true, vehicle);
+                        task_connected.store(true, Ordering::SeqCst);
+                        task_reconnected.store(true, Ordering::SeqCst);
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        let vehicle = task_vehicle.lock().unwrap().clone();
+                        let expected = format!("{}/{}/action", topic, topic_level(&vehicle));
+                        let action = String::from_utf8_lossy(&publish.payload).trim().to_string();
+
+                        if vehicle.is_empty() || publish.topic != expected {
+                            if verbose {
+                                println!("MQTT action {} for {} ignored.", action, publish.topic);
+                            }
+                        } else if !action.is_empty() && tx.send(action).is_err() {
+                            // the sink is gone
+                            return;
+                        }
+                    }
+                    // closed, not connecting again
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) => return,
+                    Ok(_) => {}
+                    Err(e) => {
+                        task_connected.store(false, Ordering::SeqCst);
+                        if !reported {
+                            eprintln!("MQTT broker {}: {}", broker, e);
+                            reported = true;
+                        }
+                        tokio::time::sleep(RETRY_INTERVAL).await;
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            topic: config.topic.clone(),
+            client,
+            values: KomsiValues::new(),
+            vehicle,
+            actions,
+            connected,
+            reconnected,
+            task: Some(task),
+        })
+    }
+
+    fn publish(&self, topic: String, payload: String) -> io::Result<()> {
+        self.client
+            .try_publish(topic, QoS::AtMostOnce, true, payload)
+            .map_err(io::Error::other)
+    }
+
+    fn publish_values(&self, values: &[(char, String)]) -> io::Result<()> {
+        let vehicle = self.vehicle.lock().unwrap().clone();
+        if vehicle.is_empty() {
+            return Ok(());
+        }
+
+        for (code, digits) in values {
+            // codes without a name are not published, they are not part of the state
+            if let Some(name) = code_name(*code) {
+                let topic = format!("{}/{}/{}", self.topic, topic_level(&vehicle), name);
+                self.publish(topic, payload(*code, digits))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MqttSink {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+impl KomsiSink for MqttSink {
+    fn name(&self) -> String {
+        format!("mqtt {}", self.topic)
+    }
+
+    /// Without the broker the values are only kept, the whole state is published after reconnecting.
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        let changes = self.values.apply_frame(frame);
+        if !self.connected() {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        self.publish_values(&changes)
+    }
+
+    fn send_full_state(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.values.apply_frame(frame);
+        if !self.connected() {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        self.publish_values(&self.values.all())
+    }
+
+    fn reconnected(&mut self) -> bool {
+        self.reconnected.swap(false, Ordering::SeqCst)
+    }
+
+    fn connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    /// Without the broker the task publishes the vehicle after reconnecting.
+    fn vehicle_changed(&mut self, vehicle_name: &str) {
+        *self.vehicle.lock().unwrap() = vehicle_name.to_string();
+        if !self.connected() {
+            return;
+        }
+        let topic = format!("{}/vehicle", self.topic);
+        if let Err(e) = self.publish(topic, vehicle_name.to_string()) {
+            eprintln!("Error writing to {}: {}", self.name(), e);
+        }
+        // the values are the same until the next frame, but under the topics of the new vehicle
+        if let Err(e) = self.publish_values(&self.values.all()) {
+            eprintln!("Error writing to {}: {}", self.name(), e);
+        }
+    }
+
+    fn receive_actions(&mut self) -> Vec<String> {
+        self.actions.try_iter().collect()
+    }
+
+    /// Says goodbye, if the broker does not get it in time it sends the last will instead.
+    fn close(&mut self) -> io::Result<()> {
+        let status_topic = format!("{}/status", self.topic);
+        self.client
+            .try_publish(status_topic, QoS::AtLeastOnce, true, "offline")
+            .map_err(io::Error::other)?;
+        self.client.try_disconnect().map_err(io::Error::other)
+    }
+
+    /// The task ends after the disconnect was sent.
+    fn closing(&mut self) -> Option<JoinHandle<()>> {
+        self.task.take()
+    }
+}
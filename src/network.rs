@@ -0,0 +1,763 @@
+// KOMSI over the network, for dashboards on Wi-Fi (e.g. ESP32).
+//
+// The same byte stream as on the serial ports is sent over
+// - TCP as client, the dashboard is the server
+// - TCP as server, any number of dashboards connect
+// - UDP to a single address or a broadcast address
+//
+// [network]
+// tcp = 192.168.1.50:5000
+// tcpserver = 0.0.0.0:5000
+// udp = 192.168.1.255:5000
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures_util::future::join_all;
+use komsi::KomsiCommand;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{lookup_host, TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, timeout, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+
+use crate::input::KomsiDecoder;
+use crate::sink::KomsiSink;
+
+/// Name of the config section with the network sinks
+pub const NETWORK_SECTION: &str = "network";
+
+/// A lost connection is tried again after this time
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long connecting to a dashboard may take, including the name lookup
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// A dashboard which does not take the data within this time is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// UDP does not notice a restarted dashboard (or lost packets), so the whole state is sent regularly
+const UDP_RESYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Kind and address of a network sink
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkTarget {
+    /// connect to a dashboard, e.g. `192.168.1.50:5000` or `esp32.local:5000`
+    TcpClient(String),
+    /// dashboards connect to us, e.g. `0.0.0.0:5000`
+    TcpServer(String),
+    /// send datagrams, the address can be a broadcast address
+    Udp(String),
+}
+
+impl NetworkTarget {
+    /// Config keys of the kinds, up to 5 of each kind (`tcp`, `tcp2`, ... `tcp5`)
+    pub const KEYS: [&'static str; 3] = ["tcp", "tcpserver", "udp"];
+
+    pub fn from_key(key: &str, address: &str) -> Option<Self> {
+        let address = address.to_string();
+        match key {
+            "tcp" => Some(NetworkTarget::TcpClient(address)),
+            "tcpserver" => Some(NetworkTarget::TcpServer(address)),
+            "udp" => Some(NetworkTarget::Udp(address)),
+            _ => None,
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        match self {
+            NetworkTarget::TcpClient(a) | NetworkTarget::TcpServer(a) | NetworkTarget::Udp(a) => a,
+        }
+    }
+}
+
+impl fmt::Display for NetworkTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkTarget::TcpClient(a) => write!(f, "tcp {}", a),
+            NetworkTarget::TcpServer(a) => write!(f, "tcpserver {}", a),
+            NetworkTarget::Udp(a) => write!(f, "udp {}", a),
+        }
+    }
+}
+
+/// Settings shared by all network sinks
+#[derive(Debug, Clone, Default)]
+pub struct NetworkOptions {
+    /// Sent after a dashboard was connected
+    pub init: Vec<u8>,
+    pub verbose: bool,
+    /// Stops the tasks of all network sinks, the last frames are written until `close`
+    pub shutdown: CancellationToken,
+}
+
+/// Opens a network sink, errors are only returned if the address cannot be used at all.
+/// Needs a tokio runtime, every sink works in its own task.
+pub fn open_network_sink(
+    target: &NetworkTarget,
+    options: NetworkOptions,
+) -> io::Result<Box<dyn KomsiSink>> {
+    Ok(match target {
+        NetworkTarget::TcpClient(address) => Box::new(TcpClientSink::connect(address, options)),
+        NetworkTarget::TcpServer(address) => Box::new(TcpServerSink::bind(address, options)?),
+        NetworkTarget::Udp(address) => Box::new(UdpSink::bind(address, options)),
+    })
+}
+
+/// What the sink gives its task
+#[derive(Debug)]
+enum Frame {
+    /// for every dashboard
+    All(Vec<u8>),
+    /// the whole state, only for the dashboards connected since the last one
+    FullState(Vec<u8>),
+}
+
+/// What the task of a network sink tells the sink
+#[derive(Debug, Default)]
+struct NetworkState {
+    /// connected dashboards, 1 for a connected client and a resolved UDP address
+    connections: AtomicUsize,
+    reconnected: AtomicBool,
+}
+
+impl NetworkState {
+    fn set_connections(&self, connections: usize) {
+        self.connections.store(connections, Ordering::SeqCst);
+    }
+}
+
+/// The sink side of a network task, like `SerialSink` the sink only queues the frames
+/// and never waits for the network.
+struct NetworkTask {
+    state: Arc<NetworkState>,
+    frames: Option<mpsc::UnboundedSender<Frame>>,
+    received: mpsc::UnboundedReceiver<KomsiCommand>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl NetworkTask {
+    fn spawn<F>(
+        run: impl FnOnce(mpsc::UnboundedReceiver<Frame>, mpsc::UnboundedSender<KomsiCommand>, Arc<NetworkState>) -> F,
+    ) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let state = Arc::new(NetworkState::default());
+        let (frames, frames_rx) = mpsc::unbounded_channel();
+        let (received_tx, received) = mpsc::unbounded_channel();
+        let task = tokio::spawn(run(frames_rx, received_tx, Arc::clone(&state)));
+
+        Self {
+            state,
+            frames: Some(frames),
+            received,
+            task: Some(task),
+        }
+    }
+
+    fn send(&self, frame: Frame) -> io::Result<()> {
+        // the task connects again
+        if !self.connected() {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+
+        let Some(frames) = &self.frames else {
+            return Err(io::ErrorKind::NotConnected.into());
+        };
+        frames
+            .send(frame)
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+
+    fn receive(&mut self) -> Vec<KomsiCommand> {
+        std::iter::from_fn(|| self.received.try_recv().ok()).collect()
+    }
+
+    fn reconnected(&self) -> bool {
+        self.state.reconnected.swap(false, Ordering::SeqCst)
+    }
+
+    fn connected(&self) -> bool {
+        self.connections() > 0
+    }
+
+    fn connections(&self) -> usize {
+        self.state.connections.load(Ordering::SeqCst)
+    }
+
+    /// The task writes the frames still queued and closes the connections.
+    fn close(&mut self) {
+        self.frames = None;
+    }
+
+    fn closing(&mut self) -> Option<JoinHandle<()>> {
+        self.task.take()
+    }
+}
+
+// writes the whole frame, a dashboard which does not take it in time is dropped
+async fn write_frame(stream: &mut (impl AsyncWrite + Unpin), frame: &[u8]) -> io::Result<()> {
+    match timeout(WRITE_TIMEOUT, stream.write_all(frame)).await {
+        Ok(result) => result,
+        Err(_) => Err(io::ErrorKind::TimedOut.into()),
+    }
+}
+
+// the dashboard sees the end of the connection at once, not only after a timeout
+async fn close_stream(stream: &mut (impl AsyncWrite + Unpin)) {
+    let _ = timeout(WRITE_TIMEOUT, async {
+        let _ = stream.flush().await;
+        let _ = stream.shutdown().await;
+    })
+    .await;
+}
+
+// Reads what the dashboard sent, waits forever without a connection
+async fn read_stream(stream: &mut Option<TcpStream>, buffer: &mut [u8]) -> io::Result<usize> {
+    match stream {
+        Some(s) => s.read(buffer).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Connects to a dashboard and connects again if the connection was lost.
+pub struct TcpClientSink {
+    address: String,
+    task: NetworkTask,
+}
+
+impl TcpClientSink {
+    /// Needs a tokio runtime, the connection is made by the task.
+    pub fn connect(address: &str, options: NetworkOptions) -> Self {
+        let task = NetworkTask::spawn(|frames, received, state| {
+            run_tcp_client(address.to_string(), options, frames, received, state)
+        });
+
+        Self {
+            address: address.to_string(),
+            task,
+        }
+    }
+}
+
+impl KomsiSink for TcpClientSink {
+    fn name(&self) -> String {
+        format!("tcp {}", self.address)
+    }
+
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.task.send(Frame::All(frame.to_vec()))
+    }
+
+    fn receive(&mut self) -> Vec<KomsiCommand> {
+        self.task.receive()
+    }
+
+    fn reconnected(&mut self) -> bool {
+        self.task.reconnected()
+    }
+
+    fn connected(&self) -> bool {
+        self.task.connected()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.task.close();
+        Ok(())
+    }
+
+    fn closing(&mut self) -> Option<JoinHandle<()>> {
+        self.task.closing()
+    }
+}
+
+// The task of a TCP client: connects, writes the frames and reads the commands of the dashboard
+async fn run_tcp_client(
+    address: String,
+    options: NetworkOptions,
+    mut frames: mpsc::UnboundedReceiver<Frame>,
+    received: mpsc::UnboundedSender<KomsiCommand>,
+    state: Arc<NetworkState>,
+) {
+    let mut decoder = KomsiDecoder::new();
+    let mut stream: Option<TcpStream> = None;
+    let mut reported = false;
+    let mut retry = interval(RETRY_INTERVAL);
+    retry.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut buffer = [0u8; 256];
+
+    loop {
+        tokio::select! {
+            _ = options.shutdown.cancelled() => break,
+            frame = frames.recv() => {
+                let Some(Frame::All(frame) | Frame::FullState(frame)) = frame else {
+                    // closed
+                    break;
+                };
+                // frames queued before the connection was lost are dropped, the whole state follows
+                if let Some(s) = stream.as_mut()
+                    && let Err(e) = write_frame(s, &frame).await
+                {
+                    eprintln!("Error writing to {}: {}", address, e);
+                    stream = None;
+                    state.set_connections(0);
+                }
+            }
+            result = read_stream(&mut stream, &mut buffer) => match result {
+                Ok(n) if n > 0 => {
+                    for cmd in decoder.push(&buffer[..n]) {
+                        // the sink is gone, the task is not needed anymore
+                        if received.send(cmd).is_err() {
+                            return;
+                        }
+                    }
+                }
+                _ => {
+                    eprintln!("Connection to {} closed.", address);
+                    stream = None;
+                    state.set_connections(0);
+                }
+            },
+            _ = retry.tick(), if stream.is_none() => match connect(&address, &options.init).await {
+                Ok(s) => {
+                    if options.verbose {
+                        println!("Connected to {}.", address);
+                    }
+                    reported = false;
+                    decoder = KomsiDecoder::new();
+                    stream = Some(s);
+                    state.set_connections(1);
+                    state.reconnected.store(true, Ordering::SeqCst);
+                }
+                Err(e) => {
+                    if !reported {
+                        eprintln!("Failed to connect to {}: {}", address, e);
+                        reported = true;
+                    }
+                }
+            },
+        }
+    }
+
+    // the last frames (e.g. the safe state) until the sink is closed
+    if let Some(mut s) = stream {
+        while let Some(Frame::All(frame) | Frame::FullState(frame)) = frames.recv().await {
+            if write_frame(&mut s, &frame).await.is_err() {
+                break;
+            }
+        }
+        close_stream(&mut s).await;
+    }
+    state.set_connections(0);
+}
+
+// connects and sends the init frame, the name is resolved without blocking
+async fn connect(address: &str, init: &[u8]) -> io::Result<TcpStream> {
+    let mut stream = match timeout(CONNECT_TIMEOUT, TcpStream::connect(address)).await {
+        Ok(result) => result?,
+        Err(_) => return Err(io::ErrorKind::TimedOut.into()),
+    };
+    stream.set_nodelay(true)?;
+    write_frame(&mut stream, init).await?;
+    Ok(stream)
+}
+
+struct TcpClient {
+    id: u64,
+    addr: SocketAddr,
+    writer: OwnedWriteHalf,
+    /// connected since the last full state
+    fresh: bool,
+}
+
+/// Dashboards connect to us, every dashboard gets all commands.
+pub struct TcpServerSink {
+    address: String,
+    task: NetworkTask,
+}
+
+impl TcpServerSink {
+    /// Needs a tokio runtime, the dashboards are accepted by the task.
+    pub fn bind(address: &str, options: NetworkOptions) -> io::Result<Self> {
+        let listener = std::net::TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        let address = listener.local_addr()?.to_string();
+
+        let task = NetworkTask::spawn(|frames, received, state| {
+            run_tcp_server(listener, options, frames, received, state)
+        });
+
+        Ok(Self { address, task })
+    }
+
+    /// The address the server listens on
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Number of connected dashboards
+    pub fn clients(&self) -> usize {
+        self.task.connections()
+    }
+}
+
+impl KomsiSink for TcpServerSink {
+    fn name(&self) -> String {
+        format!("tcpserver {}", self.address)
+    }
+
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.task.send(Frame::All(frame.to_vec()))
+    }
+
+    /// Only the dashboards connected since the last full state get it.
+    fn send_full_state(&mut self, frame: &[u8]) -> io::Result<()> {
+        match self.task.send(Frame::FullState(frame.to_vec())) {
+            Err(e) if e.kind() == io::ErrorKind::NotConnected => Ok(()),
+            result => result,
+        }
+    }
+
+    fn receive(&mut self) -> Vec<KomsiCommand> {
+        self.task.receive()
+    }
+
+    fn reconnected(&mut self) -> bool {
+        self.task.reconnected()
+    }
+
+    fn connected(&self) -> bool {
+        self.task.connected()
+    }
+
+    /// The dashboards are disconnected after the last frames, the listener is closed at shutdown.
+    fn close(&mut self) -> io::Result<()> {
+        self.task.close();
+        Ok(())
+    }
+
+    fn closing(&mut self) -> Option<JoinHandle<()>> {
+        self.task.closing()
+    }
+}
+
+// The task of a TCP server: accepts dashboards and writes the frames to all of them,
+// every dashboard has a task reading its commands
+async fn run_tcp_server(
+    listener: TcpListener,
+    options: NetworkOptions,
+    mut frames: mpsc::UnboundedReceiver<Frame>,
+    received: mpsc::UnboundedSender<KomsiCommand>,
+    state: Arc<NetworkState>,
+) {
+    // the readers end with the server
+    let stop = options.shutdown.child_token();
+    let _stop_readers = stop.clone().drop_guard();
+    let (closed_tx, mut closed) = mpsc::unbounded_channel();
+    let mut clients: Vec<TcpClient> = Vec::new();
+    let mut next_id = 0;
+
+    loop {
+        tokio::select! {
+            _ = options.shutdown.cancelled() => break,
+            accepted = listener.accept() => {
+                let Ok((stream, addr)) = accepted else {
+                    continue;
+                };
+                let Ok(client) = accept(stream, addr, next_id, &options.init, &received, &closed_tx, &stop).await else {
+                    continue;
+                };
+                next_id += 1;
+                if options.verbose {
+                    println!("Dashboard {} connected.", addr);
+                }
+                clients.push(client);
+                state.set_connections(clients.len());
+                state.reconnected.store(true, Ordering::SeqCst);
+            }
+            Some(id) = closed.recv() => {
+                if let Some(i) = clients.iter().position(|c| c.id == id) {
+                    let client = clients.remove(i);
+                    if options.verbose {
+                        println!("Dashboard {} disconnected.", client.addr);
+                    }
+                    state.set_connections(clients.len());
+                }
+            }
+            frame = frames.recv() => {
+                let Some(frame) = frame else {
+                    // closed
+                    break;
+                };
+                write_clients(&mut clients, frame).await;
+                state.set_connections(clients.len());
+            }
+        }
+    }
+
+    // no new dashboards, but the last frames (e.g. the safe state) still go out until the sink is closed
+    drop(listener);
+    while let Some(frame) = frames.recv().await {
+        write_clients(&mut clients, frame).await;
+    }
+    state.set_connections(0);
+    for mut client in clients {
+        close_stream(&mut client.writer).await;
+    }
+}
+
+// sends the init frame and starts reading the commands of the dashboard
+async fn accept(
+    stream: TcpStream,
+    addr: SocketAddr,
+    id: u64,
+    init: &[u8],
+    received: &mpsc::UnboundedSender<KomsiCommand>,
+    closed: &mpsc::UnboundedSender<u64>,
+    stop: &CancellationToken,
+) -> io::Result<TcpClient> {
+    stream.set_nodelay(true)?;
+    let (mut reader, mut writer) = stream.into_split();
+    write_frame(&mut writer, init).await?;
+
+    let received = received.clone();
+    let closed = closed.clone();
+    let stop = stop.clone();
+    tokio::spawn(async move {
+        let mut decoder = KomsiDecoder::new();
+        let mut buffer = [0u8; 256];
+        loop {
+            tokio::select! {
+                _ = stop.cancelled() => return,
+                result = reader.read(&mut buffer) => match result {
+                    Ok(n) if n > 0 => {
+                        for cmd in decoder.push(&buffer[..n]) {
+                            let _ = received.send(cmd);
+                        }
+                    }
+                    _ => {
+                        let _ = closed.send(id);
+                        return;
+                    }
+                },
+            }
+        }
+    });
+
+    Ok(TcpClient {
+        id,
+        addr,
+        writer,
+        fresh: true,
+    })
+}
+
+// writes to all dashboards at the same time, the ones which fail are dropped
+async fn write_clients(clients: &mut Vec<TcpClient>, frame: Frame) {
+    let (frame, only_fresh) = match frame {
+        Frame::All(frame) => (frame, false),
+        Frame::FullState(frame) => (frame, true),
+    };
+
+    let results = join_all(clients.iter_mut().map(|c| {
+        let write = !only_fresh || c.fresh;
+        if only_fresh {
+            c.fresh = false;
+        }
+        let frame = &frame;
+        async move {
+            if write {
+                write_frame(&mut c.writer, frame).await
+            } else {
+                Ok(())
+            }
+        }
+    }))
+    .await;
+
+    let mut results = results.into_iter();
+    clients.retain(|_| results.next().is_some_and(|r| r.is_ok()));
+}
+
+/// Sends every frame as one datagram. Commands sent back by the dashboards are received, too.
+pub struct UdpSink {
+    address: String,
+    task: NetworkTask,
+    last_resync: Instant,
+}
+
+impl UdpSink {
+    /// Needs a tokio runtime, the address is resolved by the task.
+    pub fn bind(address: &str, options: NetworkOptions) -> Self {
+        let task = NetworkTask::spawn(|frames, received, state| {
+            run_udp(address.to_string(), options, frames, received, state)
+        });
+
+        Self {
+            address: address.to_string(),
+            task,
+            last_resync: Instant::now(),
+        }
+    }
+}
+
+impl KomsiSink for UdpSink {
+    fn name(&self) -> String {
+        format!("udp {}", self.address)
+    }
+
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.task.send(Frame::All(frame.to_vec()))
+    }
+
+    /// A restarted dashboard also needs the init frame, the task puts it in front.
+    fn send_full_state(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.task.send(Frame::FullState(frame.to_vec()))
+    }
+
+    fn receive(&mut self) -> Vec<KomsiCommand> {
+        self.task.receive()
+    }
+
+    fn reconnected(&mut self) -> bool {
+        if self.task.reconnected() {
+            self.last_resync = Instant::now();
+            return true;
+        }
+        if self.task.connected() && self.last_resync.elapsed() >= UDP_RESYNC_INTERVAL {
+            self.last_resync = Instant::now();
+            return true;
+        }
+        false
+    }
+
+    fn connected(&self) -> bool {
+        self.task.connected()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.task.close();
+        Ok(())
+    }
+
+    fn closing(&mut self) -> Option<JoinHandle<()>> {
+        self.task.closing()
+    }
+}
+
+// The task of a UDP sink: resolves the address, sends the datagrams and reads what comes back
+async fn run_udp(
+    address: String,
+    options: NetworkOptions,
+    mut frames: mpsc::UnboundedReceiver<Frame>,
+    received: mpsc::UnboundedSender<KomsiCommand>,
+    state: Arc<NetworkState>,
+) {
+    let mut retry = interval(RETRY_INTERVAL);
+    retry.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut reported = false;
+
+    // the name may not be known yet, e.g. esp32.local before the dashboard is up
+    let (socket, target) = loop {
+        tokio::select! {
+            _ = options.shutdown.cancelled() => return,
+            frame = frames.recv() => if frame.is_none() {
+                return;
+            },
+            _ = retry.tick() => match udp_socket(&address).await {
+                Ok(bound) => break bound,
+                Err(e) => {
+                    if !reported {
+                        eprintln!("Error opening udp {}: {}", address, e);
+                        reported = true;
+                    }
+                }
+            },
+        }
+    };
+
+    // frames queued from now on are sent after the init frame
+    state.set_connections(1);
+    // nobody may be listening yet, so errors do not matter
+    let _ = socket.send_to(&options.init, target).await;
+    state.reconnected.store(true, Ordering::SeqCst);
+
+    let mut decoder = KomsiDecoder::new();
+    let mut buffer = [0u8; 512];
+    let mut reported = false;
+    loop {
+        tokio::select! {
+            _ = options.shutdown.cancelled() => break,
+            frame = frames.recv() => {
+                let Some(frame) = frame else {
+                    // closed
+                    break;
+                };
+                send_datagram(&socket, target, &options.init, frame, &address, &mut reported).await;
+            }
+            result = socket.recv_from(&mut buffer) => {
+                if let Ok((n, _)) = result {
+                    for cmd in decoder.push(&buffer[..n]) {
+                        // the sink is gone, the task is not needed anymore
+                        if received.send(cmd).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // the last frames (e.g. the safe state) until the sink is closed
+    while let Some(frame) = frames.recv().await {
+        send_datagram(&socket, target, &options.init, frame, &address, &mut reported).await;
+    }
+    state.set_connections(0);
+}
+
+async fn udp_socket(address: &str) -> io::Result<(UdpSocket, SocketAddr)> {
+    let target = lookup_host(address)
+        .await?
+        .next()
+        .ok_or(io::ErrorKind::AddrNotAvailable)?;
+
+    let socket = UdpSocket::bind(if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })
+    .await?;
+    socket.set_broadcast(true)?;
+    Ok((socket, target))
+}
+
+// an error is reported once until sending works again
+async fn send_datagram(
+    socket: &UdpSocket,
+    target: SocketAddr,
+    init: &[u8],
+    frame: Frame,
+    address: &str,
+    reported: &mut bool,
+) {
+    let datagram = match frame {
+        Frame::All(frame) => frame,
+        Frame::FullState(frame) => [init, &frame].concat(),
+    };
+    match socket.send_to(&datagram, target).await {
+        Ok(_) => *reported = false,
+        Err(e) if !*reported => {
+            eprintln!("Error writing to udp {}: {}", address, e);
+            *reported = true;
+        }
+        Err(_) => {}
+    }
+}
@@ -1,5 +1,9 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 
+use crate::config::{Overrides, Source, ENV_CONFIG};
+
 #[derive(Parser, Debug)]
 pub struct Opts {
     /// enable debugging
@@ -21,4 +25,104 @@ pub struct Opts {
     /// enable verbose output
     #[arg(short, long)]
     pub verbose: bool,
+
+    /// check the config file, the ports and the connection to TheBus, then exit
+    #[arg(long)]
+    pub check_config: bool,
+
+    /// find the dashboard and TheBus, then write the config file
+    #[arg(long, conflicts_with = "check_config")]
+    pub setup: bool,
+
+    /// send a test pattern to the ports of the config file, TheBus is not needed
+    #[arg(long, conflicts_with_all = ["check_config", "setup"])]
+    pub test_pattern: bool,
+
+    /// type KOMSI commands for the first port (or --port) and see what the device sends
+    #[arg(long, conflicts_with_all = ["check_config", "setup", "test_pattern"])]
+    pub console: bool,
+
+    /// record all API responses to a JSON lines file
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// replay a recorded file instead of reading the API
+    #[arg(long, value_name = "FILE")]
+    pub replay: Option<PathBuf>,
+
+    /// replay speed, 2 = twice as fast
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0, requires = "replay")]
+    pub replay_speed: f64,
+
+    /// config file, instead of searching TheBus2Komsi.ini
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// IP address of TheBus, instead of ip in the config file
+    #[arg(long)]
+    pub ip: Option<String>,
+
+    /// serial port or USB id (VID:PID), can be repeated, replaces the ports of the config file
+    #[arg(short, long = "port", value_name = "PORT")]
+    pub ports: Vec<String>,
+
+    /// baudrate of all ports
+    #[arg(short, long)]
+    pub baudrate: Option<u32>,
+
+    /// poll interval in ms
+    #[arg(short, long)]
+    pub sleeptime: Option<u64>,
+}
+
+impl Opts {
+    /// The config file given on the command line or in THEBUS2KOMSI_CONFIG
+    pub fn config_file(&self) -> Option<PathBuf> {
+        self.config
+            .clone()
+            .or_else(|| std::env::var_os(ENV_CONFIG).map(PathBuf::from))
+    }
+
+    /// The settings of the command line on top of those of the environment
+    pub fn overrides(&self) -> Overrides {
+        let mut overrides = Overrides::from_env(|var| std::env::var(var).ok());
+
+        if let Some(ip) = &self.ip {
+            overrides.ip = Some((ip.clone(), Source::CommandLine));
+        }
+        if !self.ports.is_empty() {
+            overrides.ports = Some((self.ports.clone(), Source::CommandLine));
+        }
+        if let Some(baudrate) = self.baudrate {
+            overrides.baudrate = Some((baudrate, Source::CommandLine));
+        }
+        if let Some(sleeptime) = self.sleeptime {
+            overrides.sleeptime = Some((sleeptime, Source::CommandLine));
+        }
+        overrides
+    }
+}
+
+/// Options of TheBusMockAPI
+#[derive(Parser, Debug)]
+pub struct MockOpts {
+    /// port of the API
+    #[arg(short, long, default_value_t = 37337)]
+    pub port: u16,
+
+    /// directory with the vehicle JSON files
+    #[arg(long, value_name = "DIR", default_value = "tests/json")]
+    pub fixtures: PathBuf,
+
+    /// vehicle the player sits in (ActorName), default is the first one
+    #[arg(long, value_name = "NAME")]
+    pub vehicle: Option<String>,
+
+    /// drive the scripted scenario with the vehicle
+    #[arg(long)]
+    pub scenario: bool,
+
+    /// number of requests each step of the scenario is served
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    pub frame_requests: u64,
 }
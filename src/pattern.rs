@@ -0,0 +1,106 @@
+// Test pattern for dashboard builders (--test-pattern): every lamp, gauge and the clock
+// get known values, so the wiring and the calibration can be checked without TheBus.
+
+use std::time::Duration;
+
+use komsi::{KomsiCommand, KomsiDateTime};
+
+use crate::bridge::{safe_state_frame, DEFAULT_SAFE_STATE};
+
+/// Arduinos restart when the port is opened, the test pattern waits for them
+pub(crate) const BOARD_START: Duration = Duration::from_secs(2);
+
+/// How long a lamp stays on
+const LAMP_TIME: Duration = Duration::from_millis(600);
+
+/// Time between two values of a gauge sweep
+const SWEEP_TIME: Duration = Duration::from_millis(300);
+
+/// How long each indicator state is shown
+const INDICATOR_TIME: Duration = Duration::from_secs(2);
+
+/// How long the clock is shown
+const CLOCK_TIME: Duration = Duration::from_secs(3);
+
+/// A frame and how long it is shown
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternStep {
+    /// What should be seen, e.g. `lamp C`
+    pub name: String,
+    pub frame: Vec<u8>,
+    pub hold: Duration,
+}
+
+impl PatternStep {
+    fn new(name: String, commands: &[KomsiCommand], hold: Duration) -> Self {
+        let mut frame = Vec::new();
+        for cmd in commands {
+            frame.extend_from_slice(&KomsiCommand::build(cmd));
+        }
+        frame.extend_from_slice(&KomsiCommand::build_eol());
+        Self { name, frame, hold }
+    }
+}
+
+/// The whole sequence: all off, lamps A to M one after the other (D is the indicator), speed and
+/// max speed from 0 to 100, fuel from 0 to 100, the indicator states, the clock and all off again.
+pub fn test_pattern(date_time: KomsiDateTime) -> Vec<PatternStep> {
+    let all_off = PatternStep {
+        name: "all off".to_string(),
+        frame: safe_state_frame(DEFAULT_SAFE_STATE).unwrap(),
+        hold: LAMP_TIME,
+    };
+    let mut steps = vec![all_off.clone()];
+
+    // the lamp before is switched off in the same frame, D is the indicator and comes later
+    let mut previous: Option<KomsiCommand> = None;
+    for code in ('A'..='M').filter(|&code| code != 'D') {
+        let on: KomsiCommand = format!("{}1", code).parse().unwrap();
+        let mut commands: Vec<KomsiCommand> = previous.take().into_iter().collect();
+        commands.push(on);
+        steps.push(PatternStep::new(format!("lamp {}", code), &commands, LAMP_TIME));
+        previous = Some(format!("{}0", code).parse().unwrap());
+    }
+    steps.push(PatternStep::new("lamp M off".to_string(), &[previous.unwrap()], LAMP_TIME));
+
+    for value in (0..=100).step_by(10) {
+        steps.push(PatternStep::new(
+            format!("speed and max speed {}", value),
+            &[KomsiCommand::Speed(value), KomsiCommand::MaxSpeed(value)],
+            SWEEP_TIME,
+        ));
+    }
+    steps.push(PatternStep::new(
+        "speed and max speed 0".to_string(),
+        &[KomsiCommand::Speed(0), KomsiCommand::MaxSpeed(0)],
+        SWEEP_TIME,
+    ));
+
+    for value in (0..=100).step_by(10) {
+        steps.push(PatternStep::new(
+            format!("fuel {}", value),
+            &[KomsiCommand::Fuel(value)],
+            SWEEP_TIME,
+        ));
+    }
+
+    for (value, name) in [(1, "left"), (2, "right"), (3, "both"), (0, "off")] {
+        steps.push(PatternStep::new(
+            format!("indicator {}", name),
+            &[KomsiCommand::Indicator(value)],
+            INDICATOR_TIME,
+        ));
+    }
+
+    steps.push(PatternStep::new(
+        format!(
+            "clock {:04}-{:02}-{:02} {:02}:{:02}",
+            date_time.year, date_time.month, date_time.day, date_time.hour, date_time.min
+        ),
+        &[KomsiCommand::DateTime(date_time)],
+        CLOCK_TIME,
+    ));
+
+    steps.push(all_off);
+    steps
+}
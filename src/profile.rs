@@ -0,0 +1,212 @@
+// Per vehicle model profiles.
+//
+// Not all bus models use the same API variables (see API-Mapping.md), a profile section
+// adapts aliases and the mapping for the models it matches:
+//
+// [profile.citea]
+// match = BP_VDL_Citea
+// AllLamps.LightHeadlight = AllLamps.LightHeadlight1
+// t = RPM, round
+//
+// `match` is a comma separated list of VehicleModel names or ActorName prefixes.
+// Keys with a single character are KOMSI codes like in [mapping],
+// all other keys are aliases: the right JSON path is renamed to the left one.
+// Renaming (instead of copying) is needed because the serde aliases of ApiLamps
+// do not allow two names of the same field in one JSON object.
+
+use std::collections::HashMap;
+
+use configparser::ini::Ini;
+use serde_json::Value;
+
+use crate::config::Problem;
+use crate::mapping::{Mapping, MappingEntry};
+
+/// Prefix of the profile section names
+pub const PROFILE_SECTION_PREFIX: &str = "profile.";
+
+#[derive(Debug, Default, Clone)]
+pub struct Profile {
+    pub name: String,
+    /// VehicleModel names or ActorName prefixes
+    pub matches: Vec<String>,
+    /// (API path used by TheBus2Komsi, API path of this model)
+    pub aliases: Vec<(String, String)>,
+    /// Mapping entries of this profile, they replace the entries of [mapping]
+    pub mapping: Mapping,
+}
+
+impl Profile {
+    pub fn from_section(
+        name: &str,
+        section: &HashMap<String, Option<String>>,
+        problems: &mut Vec<Problem>,
+    ) -> Self {
+        let section_name = format!("{}{}", PROFILE_SECTION_PREFIX, name);
+        let mut profile = Profile {
+            name: name.to_string(),
+            ..Default::default()
+        };
+
+        for (key, value) in section {
+            let value = value.as_deref().unwrap_or("").trim();
+
+            if key == "match" {
+                profile.matches = value
+                    .split(',')
+                    .map(|m| m.trim().to_string())
+                    .filter(|m| !m.is_empty())
+                    .collect();
+            } else if key.chars().count() == 1 {
+                match MappingEntry::parse(key, value) {
+                    Ok(entry) => profile.mapping.insert(entry),
+                    Err(e) => problems.push(Problem::key(
+                        &section_name,
+                        key,
+                        format!("Profile {}: mapping {} ignored: {}", name, key, e),
+                    )),
+                }
+            } else if value.is_empty() {
+                problems.push(Problem::key(
+                    &section_name,
+                    key,
+                    format!("Profile {}: alias {} has no value, ignored.", name, key),
+                ));
+            } else {
+                profile.aliases.push((key.clone(), value.to_string()));
+            }
+        }
+
+        if profile.matches.is_empty() {
+            problems.push(Problem::section(
+                &section_name,
+                format!("Profile {} has no match entry and will never be used.", name),
+            ));
+        }
+
+        profile.aliases.sort();
+        profile
+    }
+
+    /// A profile matches if the VehicleModel is equal or the ActorName starts with one of the entries.
+    pub fn matches(&self, vehicle_model: &str, actor_name: &str) -> bool {
+        let vehicle_model = vehicle_model.to_lowercase();
+        let actor_name = actor_name.to_lowercase();
+
+        self.matches.iter().any(|m| {
+            let m = m.to_lowercase();
+            vehicle_model == m || actor_name.starts_with(&m)
+        })
+    }
+
+    /// Renames the model specific API paths to the paths TheBus2Komsi reads.
+    pub fn apply_aliases(&self, vehicle: &mut Value) {
+        for (target, source) in &self.aliases {
+            if let Some(value) = take_value(vehicle, source) {
+                set_value(vehicle, target, value);
+            }
+        }
+    }
+}
+
+/// All profiles of the config file and the mapping they are based on
+#[derive(Debug, Default, Clone)]
+pub struct Profiles {
+    pub base: Mapping,
+    pub profiles: Vec<Profile>,
+}
+
+impl Profiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads `[mapping]` and all `[profile.<name>]` sections of the (case sensitive) config file.
+    /// Entries which cannot be used are ignored and added to `problems`.
+    pub fn from_ini(ini: &Ini, verbose: bool, problems: &mut Vec<Problem>) -> Self {
+        let mut profiles = Profiles {
+            base: Mapping::from_ini(ini, verbose, problems),
+            profiles: Vec::new(),
+        };
+
+        for (section_name, section) in ini.get_map_ref() {
+            if let Some(name) = section_name.strip_prefix(PROFILE_SECTION_PREFIX) {
+                let profile = Profile::from_section(name, section, problems);
+                if verbose {
+                    println!(
+                        "Profile {}: match={:?} aliases={} mappings={}",
+                        profile.name,
+                        profile.matches,
+                        profile.aliases.len(),
+                        profile.mapping.entries.len()
+                    );
+                }
+                profiles.profiles.push(profile);
+            }
+        }
+
+        // the first matching profile wins, so the order must not depend on the HashMap
+        profiles.profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        profiles
+    }
+
+    /// Returns the first profile matching the vehicle.
+    pub fn select(&self, vehicle_model: &str, actor_name: &str) -> Option<&Profile> {
+        self.profiles
+            .iter()
+            .find(|p| p.matches(vehicle_model, actor_name))
+    }
+
+    /// The mapping for the vehicle: [mapping] with the entries of the profile on top.
+    pub fn mapping(&self, profile: Option<&Profile>) -> Mapping {
+        let mut mapping = self.base.clone();
+        if let Some(p) = profile {
+            for entry in &p.mapping.entries {
+                mapping.insert(entry.clone());
+            }
+        }
+        mapping
+    }
+}
+
+/// Returns the parent of the path and the last part of the path.
+fn parent_mut<'a, 'p>(value: &'a mut Value, path: &'p str) -> (Option<&'a mut Value>, &'p str) {
+    match path.rsplit_once('.') {
+        Some((parent, key)) => (
+            parent.split('.').try_fold(value, |v, k| match v {
+                Value::Object(map) => map.get_mut(k),
+                Value::Array(list) => k.parse::<usize>().ok().and_then(|i| list.get_mut(i)),
+                _ => None,
+            }),
+            key,
+        ),
+        None => (Some(value), path),
+    }
+}
+
+/// Removes a value of an object by path.
+fn take_value(value: &mut Value, path: &str) -> Option<Value> {
+    match parent_mut(value, path) {
+        (Some(Value::Object(map)), key) => map.remove(key),
+        (Some(Value::Array(list)), key) => key
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| list.get(i).cloned()),
+        _ => None,
+    }
+}
+
+/// Sets a value by path, the parent of the last part must exist.
+fn set_value(value: &mut Value, path: &str, new_value: Value) {
+    match parent_mut(value, path) {
+        (Some(Value::Object(map)), key) => {
+            map.insert(key.to_string(), new_value);
+        }
+        (Some(Value::Array(list)), key) => {
+            if let Some(v) = key.parse::<usize>().ok().and_then(|i| list.get_mut(i)) {
+                *v = new_value;
+            }
+        }
+        _ => {}
+    }
+}
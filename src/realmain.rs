@@ -1,57 +1,36 @@
-use std::io;
-use std::io::{Read, Write};
-use std::path::Path;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::{Duration, Instant};
-
-use tokio::time::sleep;
-
-use configparser::ini::Ini;
-use komsi::vehicle::{VehicleLogger, VehicleState};
-use komsi::{KomsiCommand, KomsiDateTime};
-
-struct PrintLogger;
-
-impl VehicleLogger for PrintLogger {
-    fn log(&self, msg: String) {
-        println!("{}", msg);
-    }
-}
-use serialport::SerialPort;
-
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use crate::bridge::{close_sinks, init_frame, safe_state_frame, Bridge, BridgeOptions, Reload, DEFAULT_SAFE_STATE};
+use crate::check::ConfigCheck;
+use crate::console::Console;
+use crate::config::{
+    config_search_paths, find_config_file, Config, ConfigWatcher, NetworkConfig, Overrides,
+    PortConfig, CONFIG_FILE,
+};
+use crate::filter::{CommandFilter, FilteredSink};
+use crate::mqtt::{MqttConfig, MqttSink};
+use crate::network::{open_network_sink, NetworkOptions};
 // TODO will be removed
 use crate::opts::Opts;
-
-use the_bus_telemetry::api::{get_current_vehicle_name, get_vehicle, get_world, RequestConfig};
-use the_bus_telemetry::api2vehicle::get_vehicle_state_from_api;
-use the_bus_telemetry::ApiVehicleType;
-
-// Serial port functionality
-// This function is only included when the disablekomsiport feature is not enabled
-#[cfg(not(feature = "disablekomsiport"))]
-fn try_open_serial_port(
-    portname: &str,
-    baudrate: u32,
-    verbose: bool,
-) -> Option<Box<dyn SerialPort>> {
-    match serialport::new(portname, baudrate).open() {
-        Ok(port) => {
-            if verbose {
-                eprintln!("Port {:?} geöffnet mit {} baud.", portname, baudrate);
-            }
-            Some(port)
-        }
-        Err(e) => {
-            eprintln!("Failed to open serial port {}: {}", portname, e);
-            None
-        }
-    }
-}
+use crate::pattern::{test_pattern, BOARD_START};
+use crate::record::{RecordingSource, ReplaySource};
+use crate::serial::Hotplug;
+use crate::setup::run_setup;
+use crate::sink::{KomsiSink, SerialOptions, SerialSink};
+use crate::source::{HttpSource, TelemetrySource};
+use crate::web::WebSink;
+
+use chrono::{Datelike, Local, Timelike};
+use komsi::KomsiDateTime;
+use the_bus_telemetry::api::RequestConfig;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// How often the config file is checked for changes
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 pub async fn real_main(opts: &Opts) {
-    let debug = opts.debug;
-    let debug_serial = opts.debug_serial;
     let verbose = opts.verbose;
 
     if verbose {
@@ -59,399 +38,545 @@ pub async fn real_main(opts: &Opts) {
         println!("Version: {}", env!("CARGO_PKG_VERSION"));
     }
 
-    let mut vehicle_state = VehicleState::new();
-
-    let config_path = "TheBus2Komsi.ini";
-
-    let mut baudrate = 115200;
-    let mut sleeptime = 200;
-    let mut portnames = Vec::new();
-    let mut clientip = "127.0.0.1".to_string();
-
-    if Path::new(config_path).exists() {
-        // now we get config ini
-        let mut config_file = Ini::new();
-        let _ = config_file.load(config_path);
-
-        // Check for missing configuration values and use defaults if needed
-        match config_file.getint("default", "baudrate") {
-            Ok(Some(value)) => baudrate = value as u32,
-            Ok(None) | Err(_) => {
-                if verbose {
-                    println!("Using default baudrate: {}", baudrate);
-                }
-            }
-        }
-
-        match config_file.getint("default", "sleeptime") {
-            Ok(Some(value)) => sleeptime = value as u64,
-            Ok(None) | Err(_) => {
-                if verbose {
-                    println!("Using default sleeptime: {}", sleeptime);
-                }
-            }
-        }
-
-        let port_keys = [
-            "portname",
-            "portname2",
-            "portname3",
-            "portname4",
-            "portname5",
-        ];
-        for key in port_keys {
-            if let Some(value) = config_file.get("default", key) {
-                if !value.is_empty() {
-                    portnames.push(value);
-                }
-            }
-        }
+    let Some(config) = load_config(opts) else {
+        return;
+    };
 
-        if portnames.is_empty() {
-            if verbose {
-                println!("Using default portname: COM1");
+    // Display appropriate startup message based on feature configuration
+    let program = if cfg!(feature = "disablekomsiport") {
+        "TheBusTestAPI"
+    } else {
+        "TheBus2Komsi"
+    };
+    println!("{} {} has started. Have fun!", program, env!("CARGO_PKG_VERSION"));
+    println!("{}", config.report());
+
+    let replay = opts.replay.is_some();
+    let options = bridge_options(BridgeOptions::from_opts(opts), &config, replay);
+    let reloader = config.path.as_deref().map(|path| Reloader {
+        watcher: ConfigWatcher::new(path),
+        overrides: opts.overrides(),
+        options: BridgeOptions::from_opts(opts),
+        replay,
+    });
+
+    if let Some(replay) = &opts.replay {
+        let source = match ReplaySource::open(replay, opts.replay_speed, verbose) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("Error reading recording {}: {}", replay.display(), e);
+                return;
             }
-            portnames.push("COM1".to_string());
-        }
+        };
+        println!("Replaying {} at speed {}.", replay.display(), opts.replay_speed);
+        run_bridge(source, config, options, reloader).await;
+        println!("End of recording.");
+        return;
+    }
 
-        match config_file.get("default", "ip") {
-            Some(value) => clientip = value,
-            None => {
-                if verbose {
-                    println!("Using default IP: {}", clientip);
-                }
+    // api client config struct
+    let request_config = RequestConfig::new()
+        .host(config.ip.clone())
+        .debugging(opts.debug);
+    let source = HttpSource::new(request_config);
+
+    match &opts.record {
+        Some(record) => match RecordingSource::create(source, record) {
+            Ok(source) => {
+                println!("Recording to {}.", record.display());
+                run_bridge(source, config, options, reloader).await;
             }
-        }
-    } else if verbose {
-        println!(
-            "Config file {} not found, using default values IP: {}, portname: COM1, baudrate: {}, sleeptime: {}",
-            config_path, clientip, baudrate, sleeptime
-        );
-        portnames.push("COM1".to_string());
+            Err(e) => eprintln!("Error creating recording {}: {}", record.display(), e),
+        },
+        None => run_bridge(source, config, options, reloader).await,
     }
+}
 
-    // Display appropriate startup message based on feature configuration
-    #[cfg(feature = "disablekomsiport")]
-    println!(
-        "TheBusTestAPI {} has started. Have fun!",
-        env!("CARGO_PKG_VERSION")
-    );
-
-    #[cfg(not(feature = "disablekomsiport"))]
-    println!(
-        "TheBus2Komsi {} has started. Have fun!",
-        env!("CARGO_PKG_VERSION")
-    );
-
-    // Serial port initialization and configuration
-    // Create shared ports that can be safely accessed from multiple threads
-    #[cfg(not(feature = "disablekomsiport"))]
-    let ports = {
-        let mut p_list = Vec::new();
-        for pname in &portnames {
-            p_list.push(Arc::new(Mutex::new(try_open_serial_port(
-                pname, baudrate, verbose,
-            ))));
+/// The config file with the settings of the command line and the environment,
+/// None if the file given on the command line is not there
+fn load_config(opts: &Opts) -> Option<Config> {
+    let mut config = match find_config_file(opts.config_file().as_deref()) {
+        Ok(Some(path)) => Config::load(&path, opts.verbose),
+        Ok(None) => {
+            let searched: Vec<String> = config_search_paths()
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect();
+            eprintln!("{} not found in {}.", CONFIG_FILE, searched.join(", "));
+            Config::default()
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return None;
         }
-        p_list
     };
+    config.apply_overrides(opts.overrides());
+    Some(config)
+}
 
-    #[cfg(not(feature = "disablekomsiport"))]
-    let mut init_buffer = Vec::new();
+/// Sends the test pattern to the ports of the config file, Ctrl-C stops it.
+pub async fn run_test_pattern(opts: &Opts) {
+    let Some(config) = load_config(opts) else {
+        return;
+    };
 
-    // Send SimulatorType:TheBus initialization message if port is available
-    #[cfg(not(feature = "disablekomsiport"))]
-    {
-        init_buffer = Vec::new();
-        let simulator_type = KomsiCommand::SimulatorType(1);
-        // serialze simulator_type and datetime into buffer
-        init_buffer.extend_from_slice(&KomsiCommand::build(&simulator_type));
-        // hänge ein "\n" NEW-LINE an den Buffer
-        init_buffer.extend_from_slice(&KomsiCommand::build_eol());
+    let shutdown = CancellationToken::new();
+    let token = shutdown.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        token.cancel();
+    });
+
+    let options = BridgeOptions::from_opts(opts);
+    let specs: Vec<SinkSpec> = sink_specs(&config, opts.verbose)
+        .into_iter()
+        .filter(|spec| matches!(spec, SinkSpec::Port(_)))
+        .collect();
+    if specs.is_empty() {
+        eprintln!("No port to send the test pattern to.");
+        return;
+    }
+    let mut sinks: Vec<Box<dyn KomsiSink>> = SinkOptions::new(&options, &shutdown)
+        .open_all(specs)
+        .await
+        .into_iter()
+        .map(|(_, sink)| sink)
+        .collect();
+
+    let now = Local::now();
+    let date_time = KomsiDateTime {
+        year: now.year() as u16,
+        month: now.month() as u8,
+        day: now.day() as u8,
+        hour: now.hour() as u8,
+        min: now.minute() as u8,
+        sec: now.second() as u8,
     };
 
-    #[cfg(not(feature = "disablekomsiport"))]
-    {
-        for port in &ports {
-            let mut port_guard = port.lock().unwrap();
-            if let Some(ref mut p) = *port_guard {
-                if let Err(e) = p.write(&init_buffer) {
-                    eprintln!("Error writing to port: {}", e);
-                }
+    println!("Waiting for the boards.");
+    tokio::select! {
+        _ = shutdown.cancelled() => {}
+        _ = tokio::time::sleep(BOARD_START) => {}
+    }
+
+    for step in test_pattern(date_time) {
+        if shutdown.is_cancelled() {
+            break;
+        }
+        println!("{}", step.name);
+        for sink in sinks.iter_mut() {
+            // a port which is not open yet misses the step, like in the bridge
+            if let Err(e) = sink.send(&step.frame)
+                && e.kind() != std::io::ErrorKind::NotConnected
+            {
+                eprintln!("Error writing to port {}: {}", sink.name(), e);
             }
         }
+        tokio::select! {
+            _ = shutdown.cancelled() => {}
+            _ = tokio::time::sleep(step.hold) => {}
+        }
     }
 
-    // Prepare variables for the serial port reading threads
-    #[cfg(not(feature = "disablekomsiport"))]
-    let debug_serial_clone = debug_serial;
-    #[cfg(not(feature = "disablekomsiport"))]
-    let verbose_clone = verbose;
-
-    // api client config struct
-    let mut config = RequestConfig::new().host(clientip.clone()).debugging(debug);
-
-    // Serial port reading threads
-    // These threads continuously read data from the serial ports and handle reconnection if needed
-    #[cfg(not(feature = "disablekomsiport"))]
-    for (i, port) in ports.iter().enumerate() {
-        let port_clone = Arc::clone(port);
-        let portname_clone = portnames[i].clone();
-        let init_buffer_clone = init_buffer.clone();
-        thread::spawn(move || {
-            loop {
-                let mut need_reconnect = false;
-
-                // Try to reconnect if port is not available
-                {
-                    let mut port_guard = port_clone.lock().unwrap();
-                    if port_guard.is_none() {
-                        *port_guard =
-                            try_open_serial_port(&portname_clone, baudrate, verbose_clone);
-                        // If reconnection successful, send SimulatorType:TheBus
-                        if let Some(ref mut p) = *port_guard {
-                            if let Err(e) = p.write(&init_buffer_clone) {
-                                eprintln!(
-                                    "Error writing to port {} after reconnection: {}",
-                                    portname_clone, e
-                                );
-                                // Mark for reconnection on next iteration
-                                *port_guard = None;
-                            }
-                        }
-                    }
-                }
+    // also when stopped in the middle, nothing stays on
+    let off = safe_state_frame(DEFAULT_SAFE_STATE).unwrap();
+    for sink in sinks.iter_mut() {
+        let _ = sink.send(&off);
+    }
+    shutdown.cancel();
+    close_sinks(sinks, opts.verbose).await;
+}
 
-                // Read the bytes back from the port
-                let mut buffer: [u8; 1] = [0; 1];
-
-                // Scope for port_guard to ensure it's dropped before we try to reconnect
-                {
-                    let mut port_guard = port_clone.lock().unwrap();
-
-                    if let Some(ref mut p) = *port_guard {
-                        // Check if there are bytes to read
-                        match p.bytes_to_read() {
-                            Ok(bytes) if bytes > 0 => {
-                                if debug_serial_clone {
-                                    eprint!("REC [{}]: ", portname_clone);
-                                }
-
-                                // Read available bytes
-                                'reading: loop {
-                                    match p.bytes_to_read() {
-                                        Ok(bytes) if bytes > 0 => match p.read(&mut buffer) {
-                                            Ok(bytes) => {
-                                                if bytes > 0 && debug_serial_clone {
-                                                    eprint!("{}", buffer[0] as char);
-                                                }
-                                            }
-                                            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => (),
-                                            Err(e) => {
-                                                eprintln!(
-                                                    "Error reading from port {}: {:?}",
-                                                    portname_clone, e
-                                                );
-                                                need_reconnect = true;
-                                                break 'reading;
-                                            }
-                                        },
-                                        Ok(_) => break 'reading,
-                                        Err(e) => {
-                                            eprintln!(
-                                                "Error checking bytes to read on {}: {:?}",
-                                                portname_clone, e
-                                            );
-                                            need_reconnect = true;
-                                            break 'reading;
-                                        }
-                                    }
-                                }
-
-                                if debug_serial_clone {
-                                    eprintln!("");
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!(
-                                    "Error checking bytes to read on {}: {:?}",
-                                    portname_clone, e
-                                );
-                                need_reconnect = true;
-                            }
-                            _ => {}
-                        }
-                    }
+/// KOMSI console on the first port of the config file, or the one given with --port.
+/// The port gets no filter, everything typed is sent.
+pub async fn run_console(opts: &Opts) {
+    let Some(config) = load_config(opts) else {
+        return;
+    };
+    let Some(port) = config.ports.into_iter().find(|p| p.enabled) else {
+        eprintln!("No port for the console, give one with --port.");
+        return;
+    };
 
-                    // If we need to reconnect, set the port to None
-                    if need_reconnect {
-                        *port_guard = None;
-                    }
-                }
+    let shutdown = CancellationToken::new();
+    let token = shutdown.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        token.cancel();
+    });
+
+    let sink_options = SinkOptions::new(&BridgeOptions::from_opts(opts), &shutdown);
+    let serial_options = SerialOptions {
+        settings: port.settings,
+        ..sink_options.serial
+    };
+    let sink = Box::new(SerialSink::open(port.selector, serial_options));
 
-                // Sleep before next iteration
-                thread::sleep(Duration::from_millis(100));
-            }
-        });
-    }
+    let sink = Console::new(sink, shutdown.clone()).run().await;
+    shutdown.cancel();
+    close_sinks(vec![sink], opts.verbose).await;
+}
 
-    let sleeptime_error = 1500;
+/// Prints what is wrong with the config file, false if anything is.
+pub async fn check_config(opts: &Opts) -> bool {
+    let path = match find_config_file(opts.config_file().as_deref()) {
+        Ok(Some(path)) => path,
+        Ok(None) => {
+            let searched: Vec<String> = config_search_paths()
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect();
+            eprintln!("{} not found in {}.", CONFIG_FILE, searched.join(", "));
+            return false;
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
 
-    let interval_error = Duration::from_millis(sleeptime_error);
-    let interval = Duration::from_millis(sleeptime);
+    let mut check = ConfigCheck::read(&path);
+    check.check_ports();
+    check.check_api().await;
 
-    let mut next_time = Instant::now() + interval;
+    check.findings.sort_by_key(|f| f.line);
+    for finding in &check.findings {
+        match finding.line {
+            Some(line) => eprintln!("{}:{}: {}", path.display(), line, finding.message),
+            None => eprintln!("{}: {}", path.display(), finding.message),
+        }
+    }
 
-    let mut vehicle_name = "".to_string();
-    let mut old_vehicle_name = "".to_string();
+    if check.is_ok() {
+        println!("{} is OK.", path.display());
+    } else {
+        eprintln!("{} problem(s) in {}.", check.findings.len(), path.display());
+    }
+    check.is_ok()
+}
 
-    let mut zaehler = 0;
-    let mut last_world_update = Instant::now() - Duration::from_secs(120); // Sofort beim ersten Mal ausführen
+/// Writes the config file given on the command line, the one found, or a new one next to the program.
+pub async fn setup(opts: &Opts) {
+    let path = match opts.config_file() {
+        Some(path) => path,
+        None => match find_config_file(None) {
+            Ok(Some(path)) => path,
+            _ => config_search_paths().remove(0),
+        },
+    };
+    run_setup(&path).await;
+}
 
-    let mut get_world_update = true;
+/// The options of the command line with the intervals of the config file
+fn bridge_options(options: BridgeOptions, config: &Config, replay: bool) -> BridgeOptions {
+    let options = options
+        .interval(Duration::from_millis(config.sleeptime))
+        .slow_interval(Duration::from_millis(config.slowtime))
+        .world_interval(Duration::from_millis(config.worldtime))
+        .vehicle_name_interval(Duration::from_millis(config.vehiclenametime))
+        .safe_state(config.safe_state.clone())
+        .heartbeat_interval(Duration::from_millis(config.heartbeat));
+
+    // the recording sets the pace
+    if replay {
+        options
+            .interval(Duration::ZERO)
+            .error_interval(Duration::ZERO)
+    } else {
+        options
+    }
+}
 
-    let mut vehicle_state = VehicleState::new();
+/// What a reload of the config file needs besides the file
+struct Reloader {
+    watcher: ConfigWatcher,
+    /// the command line and the environment still win over the file
+    overrides: Overrides,
+    /// options of the command line, see `bridge_options`
+    options: BridgeOptions,
+    replay: bool,
+}
 
-    let mut force_all_variables = false;
+/// A configured sink, a reload opens those again which changed
+#[derive(Clone, PartialEq)]
+enum SinkSpec {
+    Port(PortConfig),
+    Network(NetworkConfig),
+    Web(String),
+    Mqtt(MqttConfig),
+}
 
-    loop {
-        if (vehicle_name.is_empty()) || (zaehler > 10) {
-            config.vehicle_name = "Current".to_string();
-            vehicle_name = get_current_vehicle_name(&config).await;
-            zaehler = 0;
+impl SinkSpec {
+    /// Key of the sink in the bridge
+    fn key(&self) -> String {
+        match self {
+            SinkSpec::Port(port) => format!("port.{}", port.name),
+            SinkSpec::Network(network) => format!("network.{}", network.target),
+            SinkSpec::Web(_) => "web".to_string(),
+            SinkSpec::Mqtt(_) => "mqtt".to_string(),
         }
+    }
+}
 
-        if vehicle_name.is_empty() {
-            println!("No vehicle found, not in bus.");
-            // vehicle_state = VehicleState::new();
-            // old_vehicle_name = "".to_string();
-            get_world_update = true;
-            force_all_variables = true;
-            sleep(interval_error).await;
-            //            continue;
-        };
+fn sink_specs(config: &Config, verbose: bool) -> Vec<SinkSpec> {
+    let mut specs = Vec::new();
 
-        if config.debugging {
-            println!("Vehicle-Name: {}", vehicle_name);
+    // TheBusTestAPI only tests the API, so it does not write to the dashboards
+    if !cfg!(feature = "disablekomsiport") {
+        for port in &config.ports {
+            if !port.enabled {
+                if verbose {
+                    println!("Port {} is disabled.", port.name);
+                }
+                continue;
+            }
+            specs.push(SinkSpec::Port(port.clone()));
         }
+        specs.extend(config.network.iter().cloned().map(SinkSpec::Network));
+    }
 
-        config.vehicle_name = vehicle_name.clone();
-
-        let vehicle = {
-            if vehicle_name.is_empty() {
-                ApiVehicleType::new()
-            } else {
-                let vehicle_response = get_vehicle(&config).await;
-                if vehicle_response.is_err() {
-                    println!("Error getting vehicle data in JSON.");
-                    vehicle_name = "".to_string();
-                    get_world_update = true;
-                    sleep(interval_error).await;
-                    ApiVehicleType::new()
+    // the web server only shows the state, so TheBusTestAPI has it too
+    specs.extend(config.web.clone().map(SinkSpec::Web));
+    // like the web server MQTT needs no KOMSI hardware, so TheBusTestAPI has it too
+    specs.extend(config.mqtt.clone().map(SinkSpec::Mqtt));
+    specs
+}
+
+/// Settings shared by the sinks, also those opened by a reload
+#[derive(Clone)]
+struct SinkOptions {
+    serial: SerialOptions,
+    network: NetworkOptions,
+    verbose: bool,
+}
+
+impl SinkOptions {
+    fn new(options: &BridgeOptions, shutdown: &CancellationToken) -> Self {
+        Self {
+            serial: SerialOptions {
+                init: init_frame(),
+                verbose: options.verbose,
+                debug_serial: options.debug_serial,
+                hotplug: if cfg!(feature = "disablekomsiport") {
+                    Hotplug::default()
                 } else {
-                    zaehler += 1;
-                    vehicle_response.unwrap()
+                    Hotplug::start(options.verbose)
+                },
+                shutdown: shutdown.clone(),
+                ..Default::default()
+            },
+            network: NetworkOptions {
+                init: init_frame(),
+                verbose: options.verbose,
+                shutdown: shutdown.clone(),
+            },
+            verbose: options.verbose,
+        }
+    }
+
+    async fn open(&self, spec: &SinkSpec) -> Option<Box<dyn KomsiSink>> {
+        match spec {
+            SinkSpec::Port(port) => {
+                let options = SerialOptions {
+                    settings: port.settings.clone(),
+                    ..self.serial.clone()
+                };
+                let sink = Box::new(SerialSink::open(port.selector.clone(), options));
+                Some(filtered(sink, port.filter.clone()))
+            }
+            SinkSpec::Network(network) => {
+                match open_network_sink(&network.target, self.network.clone()) {
+                    Ok(sink) => Some(filtered(sink, network.filter.clone())),
+                    Err(e) => {
+                        eprintln!("Error opening {}: {}", network.target, e);
+                        None
+                    }
                 }
             }
-        };
-
-        if config.vehicle_model != vehicle.vehicle_model {
-            config.vehicle_model = vehicle.vehicle_model.clone();
+            SinkSpec::Web(address) => match WebSink::bind(address, self.verbose).await {
+                Ok(sink) => {
+                    println!("Vehicle state on http://{}/", sink.address());
+                    Some(Box::new(sink))
+                }
+                Err(e) => {
+                    eprintln!("Error starting web server on {}: {}", address, e);
+                    None
+                }
+            },
+            SinkSpec::Mqtt(mqtt) => match MqttSink::connect(mqtt, self.verbose) {
+                Ok(sink) => Some(filtered(Box::new(sink), mqtt.filter.clone())),
+                Err(e) => {
+                    eprintln!("Error connecting to MQTT broker {}: {}", mqtt.broker, e);
+                    None
+                }
+            },
         }
+    }
 
-        if verbose && old_vehicle_name.is_empty() && !vehicle_name.is_empty() {
-            println!("Hingesetzt. Jetzt gehts los!");
+    async fn open_all(self, specs: Vec<SinkSpec>) -> Vec<(String, Box<dyn KomsiSink>)> {
+        let mut sinks = Vec::new();
+        for spec in specs {
+            if let Some(sink) = self.open(&spec).await {
+                sinks.push((spec.key(), sink));
+            }
         }
+        sinks
+    }
+}
 
-        if vehicle_name != old_vehicle_name {
-            if verbose {
-                println!(
-                    "Vehicle is now: model={} name={}",
-                    config.vehicle_model, vehicle_name
-                );
-            }
+async fn run_bridge<S: TelemetrySource>(
+    source: S,
+    config: Config,
+    options: BridgeOptions,
+    reloader: Option<Reloader>,
+) {
+    // stops the poll loop and the tasks of the sinks
+    let shutdown = CancellationToken::new();
+    let token = shutdown.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        token.cancel();
+    });
+
+    let sink_options = SinkOptions::new(&options, &shutdown);
+    let specs = sink_specs(&config, options.verbose);
+
+    let mut bridge = Bridge::new(source)
+        .options(options.clone())
+        .input_map(config.input_map)
+        .profiles(config.profiles);
+    for (key, sink) in sink_options.clone().open_all(specs.clone()).await {
+        bridge = bridge.keyed_sink(&key, sink);
+    }
 
-            old_vehicle_name = vehicle_name.clone();
-        }
+    if let Some(reloader) = reloader {
+        let (reloads, receiver) = mpsc::unbounded_channel();
+        bridge = bridge.reloads(receiver);
+        tokio::spawn(watch_config(
+            reloader,
+            config.ip,
+            specs,
+            sink_options,
+            reloads,
+            shutdown.clone(),
+        ));
+    }
 
-        // now we can process
+    bridge.run_until(shutdown.cancelled()).await;
+    // also when a replay ended, the tasks of the sinks are not needed anymore
+    shutdown.cancel();
+    println!("{}", bridge.close().await);
+}
 
-        let mut new_vehicle_state = {
-            if vehicle_name.is_empty() {
-                VehicleState::new()
-            } else {
-                get_vehicle_state_from_api(vehicle)
-            }
-        };
+/// Reads the config file again when it was saved. A file with problems is not used at all,
+/// otherwise the sinks which changed are opened again and the rest applies with the next poll.
+async fn watch_config(
+    mut reloader: Reloader,
+    ip: String,
+    mut specs: Vec<SinkSpec>,
+    sink_options: SinkOptions,
+    reloads: mpsc::UnboundedSender<Reload>,
+    shutdown: CancellationToken,
+) {
+    let verbose = reloader.options.verbose;
+    let mut check = tokio::time::interval(CONFIG_CHECK_INTERVAL);
 
-        if config.debugging {
-            new_vehicle_state.print();
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            _ = check.tick() => {}
         }
-        new_vehicle_state.datetime = vehicle_state.datetime;
-
-        // ONLY every minute but only if we have a valid vehicle
-        if !vehicle_name.is_empty()
-            && (get_world_update || last_world_update.elapsed() >= Duration::from_secs(60))
-        {
-            last_world_update = Instant::now();
-            get_world_update = false;
-
-            // now we check the world
-            let api_world_response = get_world(&config).await;
-            if api_world_response.is_err() {
-                println!("Error getting world data.");
-            } else {
-                let date_time_str = api_world_response.unwrap().date_time;
-                let komsi_date_time = KomsiDateTime::from_iso(&date_time_str);
-                if komsi_date_time.is_ok() {
-                    new_vehicle_state.datetime = komsi_date_time.unwrap();
-                }
-            }
+        if !reloader.watcher.changed() {
+            continue;
         }
 
-        // compare and create cmd buf
-        let mut logger: Option<&dyn VehicleLogger> = None;
-        if verbose {
-            logger = Some(&PrintLogger);
+        let path = reloader.watcher.path().display().to_string();
+        let mut config = Config::read(reloader.watcher.path(), verbose);
+        if !config.problems.is_empty() {
+            eprintln!("{} not reloaded, the old config is kept:", path);
+            for problem in &config.problems {
+                eprintln!("  {}", problem);
+            }
+            continue;
         }
-        let mut cmdbuf = Vec::new();
+        config.apply_overrides(reloader.overrides.clone());
 
-        if !vehicle_name.is_empty() {
-            cmdbuf = vehicle_state.compare(&new_vehicle_state, force_all_variables, logger);
-            force_all_variables = false;
-            // replace after compare for next round
-            vehicle_state = new_vehicle_state;
+        if config.ip != ip {
+            println!("ip = {} is used after a restart, still using {}.", config.ip, ip);
         }
 
-        // Send commands to the serial ports when the disablekomsiport feature is not enabled
-        #[cfg(not(feature = "disablekomsiport"))]
-        if cmdbuf.len() > 0 {
-            // we only send, when we have a vehicle name
-            if opts.debug_serial {
-                println!("SENDING -> {:?}", cmdbuf);
-            }
+        // a sink is opened again if anything of it changed, including its filter
+        let new_specs = sink_specs(&config, verbose);
+        let keys = |specs: &[SinkSpec]| specs.iter().map(SinkSpec::key).collect::<BTreeSet<_>>();
+        let with_key = |specs: &[SinkSpec], key: &str| {
+            specs
+                .iter()
+                .filter(|spec| spec.key() == key)
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let changed: Vec<String> = keys(&specs)
+            .union(&keys(&new_specs))
+            .filter(|key| with_key(&specs, key) != with_key(&new_specs, key))
+            .cloned()
+            .collect();
+        let open = new_specs
+            .iter()
+            .filter(|spec| changed.contains(&spec.key()))
+            .cloned()
+            .collect();
+        specs = new_specs;
+
+        if changed.is_empty() {
+            println!("{} reloaded.", path);
+        } else {
+            println!("{} reloaded, reopening {}.", path, changed.join(", "));
+        }
 
-            for (i, port) in ports.iter().enumerate() {
-                // Write to serial port with reconnection handling
-                let mut port_guard = port.lock().unwrap();
+        let reload = Reload {
+            options: bridge_options(reloader.options.clone(), &config, reloader.replay),
+            input_map: config.input_map,
+            profiles: config.profiles,
+            close: changed,
+            open: Box::pin(sink_options.clone().open_all(open)),
+        };
+        if reloads.send(reload).is_err() {
+            return;
+        }
+    }
+}
 
-                // Try to reconnect if port is not available
-                if port_guard.is_none() {
-                    *port_guard = try_open_serial_port(&portnames[i], baudrate, verbose);
-                }
+/// Ctrl-C, or SIGTERM on Linux
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
 
-                // Write to port if available
-                if let Some(ref mut p) = *port_guard {
-                    if let Err(e) = p.write(&cmdbuf) {
-                        eprintln!("Error writing to port {}: {}", portnames[i], e);
-                        // Port might be disconnected, set to None to trigger reconnection next time
-                        *port_guard = None;
-                    }
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
                 }
             }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
         }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+
+    println!("Stopping.");
+}
 
-        sleep(next_time - Instant::now()).await;
-        next_time += interval;
+fn filtered(sink: Box<dyn KomsiSink>, filter: CommandFilter) -> Box<dyn KomsiSink> {
+    if filter.is_all() {
+        sink
+    } else {
+        Box::new(FilteredSink::new(sink, filter))
     }
 }
@@ -0,0 +1,281 @@
+// Recording and replaying of telemetry sessions, e.g. to debug dashboard firmware
+// away from the gaming PC.
+//
+// A recording is a JSON lines file, one line per API response:
+// {"t":1200,"kind":"vehicle","data":{...}}
+// `t` is the time in ms since the start of the recording,
+// `kind` is one of vehicle_name, vehicle, world,
+// failed requests are recorded with "error" instead of "data", e.g. "timeout: vehicles/...".
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::time::{sleep_until, Instant};
+
+use crate::source::{SourceError, TelemetrySource};
+
+pub const KIND_VEHICLE_NAME: &str = "vehicle_name";
+pub const KIND_VEHICLE: &str = "vehicle";
+pub const KIND_WORLD: &str = "world";
+
+/// One line of a recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordEntry {
+    /// ms since the start of the recording
+    pub t: u64,
+    pub kind: String,
+    pub response: Result<Value, String>,
+}
+
+impl RecordEntry {
+    pub fn to_json(&self) -> Value {
+        match &self.response {
+            Ok(data) => json!({ "t": self.t, "kind": self.kind, "data": data }),
+            Err(error) => json!({ "t": self.t, "kind": self.kind, "error": error }),
+        }
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        let t = value
+            .get("t")
+            .and_then(Value::as_u64)
+            .ok_or("missing t")?;
+        let kind = value
+            .get("kind")
+            .and_then(Value::as_str)
+            .ok_or("missing kind")?
+            .to_string();
+        let response = match (value.get("data"), value.get("error")) {
+            (Some(data), _) => Ok(data.clone()),
+            (None, Some(error)) => Err(error.as_str().unwrap_or("").to_string()),
+            (None, None) => return Err("missing data or error".to_string()),
+        };
+
+        Ok(Self { t, kind, response })
+    }
+}
+
+/// Passes all calls to the inner source and writes the responses to a file.
+pub struct RecordingSource<S: TelemetrySource> {
+    inner: S,
+    writer: BufWriter<File>,
+    start: Instant,
+}
+
+impl<S: TelemetrySource> RecordingSource<S> {
+    pub fn create(inner: S, path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            inner,
+            writer: BufWriter::new(File::create(path)?),
+            start: Instant::now(),
+        })
+    }
+
+    fn record(&mut self, kind: &str, response: Result<Value, String>) {
+        let entry = RecordEntry {
+            t: self.start.elapsed().as_millis() as u64,
+            kind: kind.to_string(),
+            response,
+        };
+
+        // flush every line, so the recording is complete even if the program is killed
+        let result = writeln!(self.writer, "{}", entry.to_json()).and_then(|_| self.writer.flush());
+        if let Err(e) = result {
+            eprintln!("Error writing recording: {}", e);
+        }
+    }
+}
+
+impl<S: TelemetrySource + Send> TelemetrySource for RecordingSource<S> {
+    async fn current_vehicle_name(&mut self) -> Result<String, SourceError> {
+        let response = self.inner.current_vehicle_name().await;
+        self.record(
+            KIND_VEHICLE_NAME,
+            response
+                .as_ref()
+                .map(|name| Value::String(name.clone()))
+                .map_err(SourceError::to_record),
+        );
+        response
+    }
+
+    async fn vehicle(&mut self, name: &str) -> Result<Value, SourceError> {
+        let response = self.inner.vehicle(name).await;
+        self.record(
+            KIND_VEHICLE,
+            response.clone().map_err(|e| e.to_record()),
+        );
+        response
+    }
+
+    async fn world(&mut self) -> Result<Value, SourceError> {
+        let response = self.inner.world().await;
+        self.record(
+            KIND_WORLD,
+            response.clone().map_err(|e| e.to_record()),
+        );
+        response
+    }
+
+    async fn send_action(&mut self, name: &str, action: &str) -> Result<(), SourceError> {
+        self.inner.send_action(name, action).await
+    }
+
+    fn finished(&self) -> bool {
+        self.inner.finished()
+    }
+}
+
+/// Plays a recording back at the original pace (or faster/slower).
+///
+/// Vehicles are returned in recorded order and wait until their time has come,
+/// the world is the last one recorded before the current replay time.
+/// Vehicle names are read on a timer, so a replay may read them more or less often than
+/// the recording: names which are already past are skipped, after the last one it stays.
+pub struct ReplaySource {
+    vehicle_names: VecDeque<RecordEntry>,
+    vehicles: VecDeque<RecordEntry>,
+    worlds: VecDeque<RecordEntry>,
+    world: Option<RecordEntry>,
+    vehicle_name: Result<String, SourceError>,
+    speed: f64,
+    start: Option<Instant>,
+    replay_time: u64,
+    verbose: bool,
+}
+
+impl ReplaySource {
+    pub fn open(path: &Path, speed: f64, verbose: bool) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut entries = Vec::new();
+
+        for (num, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let entry = serde_json::from_str::<Value>(&line)
+                .map_err(|e| e.to_string())
+                .and_then(|v| RecordEntry::from_json(&v))
+                .map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} line {}: {}", path.display(), num + 1, e),
+                    )
+                })?;
+            entries.push(entry);
+        }
+
+        Ok(Self::from_entries(entries, speed, verbose))
+    }
+
+    pub fn from_entries(entries: Vec<RecordEntry>, speed: f64, verbose: bool) -> Self {
+        let mut source = Self {
+            vehicle_names: VecDeque::new(),
+            vehicles: VecDeque::new(),
+            worlds: VecDeque::new(),
+            world: None,
+            vehicle_name: Ok(String::new()),
+            speed: if speed > 0.0 { speed } else { 1.0 },
+            start: None,
+            replay_time: 0,
+            verbose,
+        };
+
+        for entry in entries {
+            match entry.kind.as_str() {
+                KIND_VEHICLE_NAME => source.vehicle_names.push_back(entry),
+                KIND_VEHICLE => source.vehicles.push_back(entry),
+                KIND_WORLD => source.worlds.push_back(entry),
+                _ => eprintln!("Unknown kind {} in recording, ignored.", entry.kind),
+            }
+        }
+
+        source
+    }
+
+    /// Waits until the entry is due and returns it.
+    async fn next(&mut self, kind: &str) -> Option<RecordEntry> {
+        let queue = match kind {
+            KIND_VEHICLE_NAME => &mut self.vehicle_names,
+            _ => &mut self.vehicles,
+        };
+        let entry = queue.pop_front()?;
+
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let due = Duration::from_secs_f64(entry.t as f64 / 1000.0 / self.speed);
+        sleep_until(start + due).await;
+
+        self.replay_time = self.replay_time.max(entry.t);
+        Some(entry)
+    }
+}
+
+impl TelemetrySource for ReplaySource {
+    async fn current_vehicle_name(&mut self) -> Result<String, SourceError> {
+        while self
+            .vehicle_names
+            .get(1)
+            .is_some_and(|next| next.t <= self.replay_time)
+        {
+            self.vehicle_names.pop_front();
+        }
+
+        if !self.vehicle_names.is_empty() {
+            self.vehicle_name = match self.next(KIND_VEHICLE_NAME).await {
+                Some(RecordEntry {
+                    response: Ok(Value::String(name)),
+                    ..
+                }) => Ok(name),
+                Some(RecordEntry {
+                    response: Err(error),
+                    ..
+                }) => Err(SourceError::from_record(&error)),
+                _ => Ok(String::new()),
+            };
+        }
+        self.vehicle_name.clone()
+    }
+
+    async fn vehicle(&mut self, _name: &str) -> Result<Value, SourceError> {
+        match self.next(KIND_VEHICLE).await {
+            Some(entry) => entry.response.map_err(|e| SourceError::from_record(&e)),
+            None => Err(SourceError::Other("end of recording".to_string())),
+        }
+    }
+
+    async fn world(&mut self) -> Result<Value, SourceError> {
+        while self
+            .worlds
+            .front()
+            .is_some_and(|w| w.t <= self.replay_time || self.world.is_none())
+        {
+            self.world = self.worlds.pop_front();
+        }
+
+        match &self.world {
+            Some(entry) => entry
+                .response
+                .clone()
+                .map_err(|e| SourceError::from_record(&e)),
+            None => Err(SourceError::Other("no world in recording".to_string())),
+        }
+    }
+
+    async fn send_action(&mut self, _name: &str, action: &str) -> Result<(), SourceError> {
+        if self.verbose {
+            println!("Replay: action {} not sent.", action);
+        }
+        Ok(())
+    }
+
+    fn finished(&self) -> bool {
+        self.vehicles.is_empty() && self.vehicle_names.is_empty()
+    }
+}
@@ -0,0 +1,171 @@
+// When the bridge reads what.
+//
+// The vehicle is polled fast (sleeptime), but not everything needs to be that fresh:
+// - slow values like fuel and temperature are only taken every slow interval,
+//   so their jitter does not flood the ports
+// - the world (date and time) and the vehicle name are read even less often
+// Without a vehicle the wait between polls grows, up to a maximum.
+
+use std::time::{Duration, Instant};
+
+use komsi::vehicle::VehicleState;
+use tokio::time::sleep;
+
+use crate::mapping::ExtraValues;
+
+/// KOMSI codes of values which change slowly:
+/// total distance, temperature, oil, fuel and water
+pub const SLOW_CODES: [char; 5] = ['o', 'v', 'w', 'x', 'z'];
+
+/// Overruns are reported at most this often
+const OVERRUN_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Something which is due every interval
+#[derive(Debug, Clone)]
+pub struct Timer {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl Timer {
+    /// The timer is due at once
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+        }
+    }
+
+    pub fn due(&self) -> bool {
+        self.last.is_none_or(|last| last.elapsed() >= self.interval)
+    }
+
+    /// Starts the next interval
+    pub fn done(&mut self) {
+        self.last = Some(Instant::now());
+    }
+
+    /// Makes the timer due at once
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+/// A wait which doubles on every use, up to a maximum
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max: max.max(base),
+            current: base,
+        }
+    }
+
+    /// The wait for now, the next one is longer
+    pub fn next_wait(&mut self) -> Duration {
+        let wait = self.current;
+        self.current = (self.current * 2).min(self.max);
+        wait
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.base;
+    }
+}
+
+/// Keeps the polls at a fixed rate and notices when they take longer than the interval.
+///
+/// A poll which is late does not make the next ones hurry, the rate starts again from now.
+#[derive(Debug, Clone)]
+pub struct Pacer {
+    interval: Duration,
+    next: Instant,
+    overruns: u32,
+    worst: Duration,
+    last_report: Option<Instant>,
+}
+
+impl Pacer {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: Instant::now() + interval,
+            overruns: 0,
+            worst: Duration::ZERO,
+            last_report: None,
+        }
+    }
+
+    /// Waits until the next poll is due.
+    pub async fn wait(&mut self) {
+        let now = Instant::now();
+
+        match self.next.checked_duration_since(now) {
+            Some(wait) => {
+                sleep(wait).await;
+                self.next += self.interval;
+            }
+            None => {
+                // without an interval there is nothing to be late for
+                if !self.interval.is_zero() {
+                    self.overruns += 1;
+                    self.worst = self.worst.max(now - self.next);
+                }
+                self.next = now + self.interval;
+            }
+        }
+    }
+
+    /// The rate starts again from now, e.g. after a poll which waited on purpose.
+    pub fn reset(&mut self) {
+        self.next = Instant::now() + self.interval;
+    }
+
+    /// Number of late polls and the longest delay since the last report,
+    /// None if there is nothing to report yet.
+    pub fn overrun_report(&mut self) -> Option<(u32, Duration)> {
+        if self.overruns == 0
+            || self
+                .last_report
+                .is_some_and(|last| last.elapsed() < OVERRUN_REPORT_INTERVAL)
+        {
+            return None;
+        }
+
+        self.last_report = Some(Instant::now());
+        let report = (self.overruns, self.worst);
+        self.overruns = 0;
+        self.worst = Duration::ZERO;
+        Some(report)
+    }
+}
+
+/// Keeps the slow values of the old state in the new one.
+pub fn keep_slow_values(
+    old: &VehicleState,
+    new: &mut VehicleState,
+    old_extra: &ExtraValues,
+    new_extra: &mut ExtraValues,
+) {
+    new.fuel = old.fuel;
+    new.total_distance = old.total_distance;
+    new.total_distance_km = old.total_distance_km;
+
+    for code in SLOW_CODES {
+        match old_extra.get(&code) {
+            Some(&value) => {
+                new_extra.insert(code, value);
+            }
+            None => {
+                new_extra.remove(&code);
+            }
+        }
+    }
+}
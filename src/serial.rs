@@ -1,5 +1,10 @@
-use serialport::{SerialPortType, available_ports};
-use nusb;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use futures_util::StreamExt;
+use nusb::hotplug::HotplugEvent;
+use serialport::{DataBits, FlowControl, Parity, SerialPortInfo, SerialPortType, UsbPortInfo, available_ports};
 
 pub fn show_serial_comports() {
     match available_ports() {
@@ -44,16 +49,27 @@ pub fn show_serial_comports() {
 }
 
 
-pub async fn show_precise_com_ports() {
+/// A serial port of a USB device, with the names the device itself reports
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComPort {
+    pub port_name: String,
+    pub usb: UsbId,
+    pub manufacturer: String,
+    pub product: String,
+    /// e.g. v1.00, n/a if unknown
+    pub version: String,
+}
+
+/// The USB serial ports. Windows often only knows generic names, so the names
+/// the devices report are preferred.
+pub async fn precise_com_ports() -> Vec<ComPort> {
     let usb_devices: Vec<nusb::DeviceInfo> = match nusb::list_devices().await {
         Ok(iter) => iter.collect(),
         _ => Vec::new(),
     };
 
     let ports = available_ports().unwrap_or_default();
-
-    println!("{:<8} | {:<20} | {:<30} | {:<22} | {}", "Port", "Hersteller", "Produkt", "Seriennummer", "Version");
-    println!("{:-<115}", "");
+    let mut com_ports = Vec::new();
 
     for p in ports {
         if let SerialPortType::UsbPort(info) = p.port_type {
@@ -105,10 +121,309 @@ pub async fn show_precise_com_ports() {
                 "n/a".to_string()
             };
 
-            println!(
-                "{:<8} | {:<20} | {:<30} | {:<22} | {}",
-                p.port_name, mfr, prd, sn, version_str
-            );
+            com_ports.push(ComPort {
+                port_name: p.port_name,
+                usb: UsbId {
+                    vid: info.vid,
+                    pid: info.pid,
+                    serial_number: Some(sn.to_string()).filter(|sn| !sn.is_empty()),
+                },
+                manufacturer: mfr.to_string(),
+                product: prd,
+                version: version_str,
+            });
+        }
+    }
+
+    com_ports
+}
+
+pub async fn show_precise_com_ports() {
+    println!("{:<8} | {:<9} | {:<20} | {:<30} | {:<22} | {}", "Port", "USB-ID", "Hersteller", "Produkt", "Seriennummer", "Version");
+    println!("{:-<127}", "");
+
+    for p in precise_com_ports().await {
+        println!(
+            "{:<8} | {:04x}:{:04x} | {:<20} | {:<30} | {:<22} | {}",
+            p.port_name,
+            p.usb.vid,
+            p.usb.pid,
+            p.manufacturer,
+            p.product,
+            p.usb.serial_number.as_deref().unwrap_or(""),
+            p.version
+        );
+    }
+}
+
+/// USB device of a dashboard, configured as `usb = VID:PID[:SERIAL]` (hex ids),
+/// e.g. `usb = 2341:0043:SERIAL123`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsbId {
+    pub vid: u16,
+    pub pid: u16,
+    pub serial_number: Option<String>,
+}
+
+impl UsbId {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut parts = s.trim().splitn(3, ':');
+        let mut hex = |what: &str| -> Result<u16, String> {
+            let part = parts.next().unwrap_or("").trim();
+            u16::from_str_radix(part, 16)
+                .map_err(|_| format!("{} of {} is not a hex number", what, s))
+        };
+
+        let vid = hex("VID")?;
+        let pid = hex("PID")?;
+        let serial_number = parts
+            .next()
+            .map(|sn| sn.trim().to_string())
+            .filter(|sn| !sn.is_empty());
+
+        Ok(Self {
+            vid,
+            pid,
+            serial_number,
+        })
+    }
+
+    /// Same check as in `show_precise_com_ports`: the ids must be equal,
+    /// the serial numbers only have to contain each other (Windows adds suffixes).
+    pub fn matches(&self, info: &UsbPortInfo) -> bool {
+        let sn_match = match (&self.serial_number, &info.serial_number) {
+            (Some(sn), Some(p_sn)) => {
+                let u = sn.to_uppercase();
+                let p = p_sn.to_uppercase();
+                p.contains(&u) || u.contains(&p)
+            }
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        self.vid == info.vid && self.pid == info.pid && sn_match
+    }
+
+    /// The port the device is plugged in at the moment
+    pub fn find_port(&self) -> Option<String> {
+        available_ports().ok()?.into_iter().find_map(|p| match p.port_type {
+            SerialPortType::UsbPort(info) if self.matches(&info) => Some(p.port_name),
+            _ => None,
+        })
+    }
+}
+
+impl fmt::Display for UsbId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vid, self.pid)?;
+        if let Some(sn) = &self.serial_number {
+            write!(f, ":{}", sn)?;
+        }
+        Ok(())
+    }
+}
+
+/// How the port of a dashboard is selected in the config
+#[derive(Debug, Clone, PartialEq)]
+pub enum PortSelector {
+    /// fixed port name, e.g. COM3 or /dev/ttyUSB0
+    Name(String),
+    /// whatever port the USB device is plugged in
+    Usb(UsbId),
+}
+
+impl PortSelector {
+    /// Name of the port to open, None if the USB device is not plugged in
+    pub fn resolve(&self) -> Option<String> {
+        match self {
+            PortSelector::Name(name) => Some(name.clone()),
+            PortSelector::Usb(id) => id.find_port(),
+        }
+    }
+}
+
+impl fmt::Display for PortSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortSelector::Name(name) => write!(f, "{}", name),
+            PortSelector::Usb(id) => write!(f, "usb {}", id),
+        }
+    }
+}
+
+/// How the DTR or RTS line is set after the port was opened.
+/// Many Arduinos reset when DTR goes on, ESP32 boards use DTR and RTS for their boot mode.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LineMode {
+    /// whatever the system does
+    #[default]
+    Keep,
+    On,
+    Off,
+    /// off and on again, forces a reset
+    Pulse,
+}
+
+impl LineMode {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.trim().to_lowercase().as_str() {
+            "" | "keep" => Ok(LineMode::Keep),
+            "on" | "true" | "1" => Ok(LineMode::On),
+            "off" | "false" | "0" => Ok(LineMode::Off),
+            "pulse" | "reset" => Ok(LineMode::Pulse),
+            _ => Err(format!("{} is not keep, on, off or pulse", s)),
         }
     }
 }
+
+/// Settings of a serial port, the defaults fit the KOMSI sketches
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortSettings {
+    pub baudrate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub flow_control: FlowControl,
+    pub dtr: LineMode,
+    pub rts: LineMode,
+}
+
+impl Default for PortSettings {
+    fn default() -> Self {
+        Self::new(115200)
+    }
+}
+
+impl PortSettings {
+    pub fn new(baudrate: u32) -> Self {
+        Self {
+            baudrate,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            flow_control: FlowControl::None,
+            dtr: LineMode::Keep,
+            rts: LineMode::Keep,
+        }
+    }
+
+    pub fn parse_data_bits(s: &str) -> Result<DataBits, String> {
+        match s.trim() {
+            "5" => Ok(DataBits::Five),
+            "6" => Ok(DataBits::Six),
+            "7" => Ok(DataBits::Seven),
+            "8" => Ok(DataBits::Eight),
+            _ => Err(format!("{} data bits are not possible, 5 to 8", s)),
+        }
+    }
+
+    pub fn parse_parity(s: &str) -> Result<Parity, String> {
+        match s.trim().to_lowercase().as_str() {
+            "none" | "n" => Ok(Parity::None),
+            "odd" | "o" => Ok(Parity::Odd),
+            "even" | "e" => Ok(Parity::Even),
+            _ => Err(format!("{} is not none, odd or even", s)),
+        }
+    }
+
+    pub fn parse_flow_control(s: &str) -> Result<FlowControl, String> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(FlowControl::None),
+            "software" | "xonxoff" => Ok(FlowControl::Software),
+            "hardware" | "rtscts" => Ok(FlowControl::Hardware),
+            _ => Err(format!("{} is not none, software or hardware", s)),
+        }
+    }
+}
+
+/// Tells the serial ports that USB devices were plugged in or removed.
+///
+/// Every event increases the generation, the ports compare it with the last one they have seen.
+/// If hot-plug events are not available, the ports fall back to retrying periodically.
+#[derive(Debug, Clone, Default)]
+pub struct Hotplug {
+    generation: Arc<AtomicU64>,
+    active: Arc<AtomicBool>,
+}
+
+impl Hotplug {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts watching the USB devices in the background.
+    pub fn start(verbose: bool) -> Self {
+        let hotplug = Self::new();
+
+        match nusb::watch_devices() {
+            Ok(mut watch) => {
+                hotplug.active.store(true, Ordering::SeqCst);
+                let h = hotplug.clone();
+                tokio::spawn(async move {
+                    while let Some(event) = watch.next().await {
+                        if verbose {
+                            match &event {
+                                HotplugEvent::Connected(d) => println!(
+                                    "USB device {:04x}:{:04x} {} connected.",
+                                    d.vendor_id(),
+                                    d.product_id(),
+                                    d.product_string().unwrap_or("")
+                                ),
+                                HotplugEvent::Disconnected(_) => {
+                                    println!("USB device disconnected.")
+                                }
+                            }
+                        }
+                        h.notify();
+                    }
+                    h.active.store(false, Ordering::SeqCst);
+                });
+            }
+            Err(e) => eprintln!("USB hot-plug not available, ports are polled: {}", e),
+        }
+
+        hotplug
+    }
+
+    /// Signals a change of the USB devices.
+    pub fn notify(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// True if events are received
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::SeqCst)
+    }
+}
+
+/// True if the port is still listed by the system
+pub fn port_exists(portname: &str) -> bool {
+    available_ports()
+        .map(|ports| port_listed(&ports, portname))
+        .unwrap_or(true)
+}
+
+/// True if the port is in the list. A link like `/dev/serial/by-id/...` is never listed,
+/// the port it points to is.
+pub fn port_listed(ports: &[SerialPortInfo], portname: &str) -> bool {
+    let target = if cfg!(unix) {
+        std::fs::canonicalize(portname)
+            .map(|path| path.to_string_lossy().into_owned())
+            .ok()
+    } else {
+        None
+    };
+    let portname = target.as_deref().unwrap_or(portname);
+    ports.iter().any(|p| same_port_name(&p.port_name, portname))
+}
+
+/// Port names are not case sensitive on Windows, com8 is COM8.
+/// Elsewhere they are file names, /dev/ttyACM0 is not /dev/ttyacm0.
+pub fn same_port_name(a: &str, b: &str) -> bool {
+    if cfg!(windows) {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
@@ -0,0 +1,225 @@
+// Setup for new users (--setup): finds the port of the dashboard, checks if TheBus answers
+// and writes a complete TheBus2Komsi.ini, the example file with the answers filled in.
+
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use komsi::KomsiCommand;
+use the_bus_telemetry::api::RequestConfig;
+
+use crate::bridge::{init_frame, safe_state_frame, DEFAULT_SAFE_STATE};
+use crate::pattern::BOARD_START;
+use crate::serial::{precise_com_ports, same_port_name, ComPort, PortSelector};
+use crate::source::{HttpSource, TelemetrySource};
+
+/// The example config, also shipped next to the program
+const EXAMPLE_INI: &str = include_str!("../TheBus2Komsi.ini");
+
+/// How long the test pattern is shown
+const PATTERN_TIME: Duration = Duration::from_secs(3);
+
+/// What the user chose
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetupAnswers {
+    pub port: PortSelector,
+    pub baudrate: u32,
+    pub ip: String,
+}
+
+/// The example config with the answers filled in, all comments are kept.
+/// A line which is not in the example is added to the `[default]` section.
+pub fn setup_ini(example: &str, answers: &SetupAnswers) -> String {
+    let mut lines: Vec<String> = example.lines().map(str::to_string).collect();
+    let mut set = |old: &str, new: String| {
+        if let Some(line) = lines.iter_mut().find(|line| line.as_str() == old) {
+            *line = new;
+            return;
+        }
+        let at = lines
+            .iter()
+            .position(|line| line.trim() == "[default]")
+            .map_or(0, |i| i + 1);
+        lines.insert(at, new);
+    };
+
+    match &answers.port {
+        PortSelector::Name(name) => set("portname = com8", format!("portname = {}", name)),
+        PortSelector::Usb(id) => {
+            set("portname = com8", "# portname = com8".to_string());
+            set("# usb = 2341:0043:SERIAL123", format!("usb = {}", id));
+        }
+    }
+    set("baudrate = 115200", format!("baudrate = {}", answers.baudrate));
+    set("ip = 127.0.0.1", format!("ip = {}", answers.ip));
+
+    let mut ini = lines.join("\n");
+    ini.push('\n');
+    ini
+}
+
+/// All lamps on, the speedometer at 50 and the fuel gauge full, so the board can be seen
+pub fn identify_frame() -> Vec<u8> {
+    let mut frame = Vec::new();
+    for code in 'A'..='M' {
+        // D is the indicator, 3 is both sides
+        let value = if code == 'D' { 3 } else { 1 };
+        let cmd: KomsiCommand = format!("{}{}", code, value).parse().unwrap();
+        frame.extend_from_slice(&KomsiCommand::build(&cmd));
+    }
+    frame.extend_from_slice(&KomsiCommand::build(&KomsiCommand::Speed(50)));
+    frame.extend_from_slice(&KomsiCommand::build(&KomsiCommand::Fuel(100)));
+    frame.extend_from_slice(&KomsiCommand::build_eol());
+    frame
+}
+
+/// Asks the questions on stdin and writes the config file to `path`.
+pub async fn run_setup(path: &Path) {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+
+    println!("TheBus2Komsi setup");
+    println!();
+
+    let ports = precise_com_ports().await;
+    if ports.is_empty() {
+        println!("No USB serial port found, is the Arduino/ESP32 plugged in?");
+    } else {
+        println!("USB serial ports:");
+        for (i, p) in ports.iter().enumerate() {
+            println!(
+                "  {}) {:<8} {} {} {} {}",
+                i + 1,
+                p.port_name,
+                p.usb,
+                p.manufacturer,
+                p.product,
+                p.version
+            );
+        }
+    }
+    println!();
+
+    let baudrate = loop {
+        let answer = ask(&mut input, "Baudrate of the dashboard", "115200");
+        match answer.parse() {
+            Ok(baudrate) => break baudrate,
+            Err(_) => println!("{} is not a number.", answer),
+        }
+    };
+
+    // the user sees which board reacts
+    while !ports.is_empty() {
+        let answer = ask(&mut input, "Number of a port to send a test pattern to, Enter to go on", "");
+        if answer.is_empty() {
+            break;
+        }
+        match choose(&ports, &answer) {
+            Some(port) => send_test_pattern(&port.port_name, baudrate).await,
+            None => println!("{} is not in the list.", answer),
+        }
+    }
+
+    let port = loop {
+        let answer = ask(&mut input, "Number or name of the port of your dashboard", "");
+        if answer.is_empty() {
+            continue;
+        }
+        match choose(&ports, &answer) {
+            // a USB device is found on whatever port it is plugged in
+            Some(port) if yes(&mut input, "Select it by its USB id, so any USB port works", true) => {
+                break PortSelector::Usb(port.usb.clone());
+            }
+            Some(port) => break PortSelector::Name(port.port_name.clone()),
+            None => break PortSelector::Name(answer),
+        }
+    };
+
+    let mut ip = ask(&mut input, "IP address of the PC with TheBus", "127.0.0.1");
+    loop {
+        let mut source = HttpSource::new(RequestConfig::new().host(ip.clone()));
+        match source.current_vehicle_name().await {
+            Ok(name) if name.is_empty() => println!("TheBus answers."),
+            Ok(name) => println!("TheBus answers, you are in {}.", name),
+            Err(e) => {
+                println!("TheBus does not answer at {}: {}", ip, e);
+                let answer = ask(&mut input, "Another IP address, Enter to keep it", "");
+                if !answer.is_empty() {
+                    ip = answer;
+                    continue;
+                }
+            }
+        }
+        break;
+    }
+
+    let answers = SetupAnswers { port, baudrate, ip };
+    println!();
+    if path.exists() && !yes(&mut input, &format!("{} exists, overwrite it", path.display()), false) {
+        println!("Nothing written.");
+        return;
+    }
+    match std::fs::write(path, setup_ini(EXAMPLE_INI, &answers)) {
+        Ok(()) => println!("{} written, start TheBus2Komsi to use it.", path.display()),
+        Err(e) => eprintln!("Error writing {}: {}", path.display(), e),
+    }
+}
+
+/// A number of the list or the name of a listed port
+fn choose<'a>(ports: &'a [ComPort], answer: &str) -> Option<&'a ComPort> {
+    match answer.parse::<usize>() {
+        Ok(n) => ports.get(n.checked_sub(1)?),
+        Err(_) => ports.iter().find(|p| same_port_name(&p.port_name, answer)),
+    }
+}
+
+/// Shows the test pattern for a moment, then the safe state
+async fn send_test_pattern(port_name: &str, baudrate: u32) {
+    let mut port = match serialport::new(port_name, baudrate)
+        .timeout(Duration::from_secs(1))
+        .open()
+    {
+        Ok(port) => port,
+        Err(e) => {
+            println!("Error opening {}: {}", port_name, e);
+            return;
+        }
+    };
+
+    println!("Sending the test pattern to {}, all lamps should light up.", port_name);
+    tokio::time::sleep(BOARD_START).await;
+    let mut result = port.write_all(&init_frame()).and_then(|_| port.write_all(&identify_frame()));
+    if result.is_ok() {
+        tokio::time::sleep(PATTERN_TIME).await;
+        result = port.write_all(&safe_state_frame(DEFAULT_SAFE_STATE).unwrap());
+    }
+    if let Err(e) = result.and_then(|_| port.flush()) {
+        println!("Error writing to {}: {}", port_name, e);
+    }
+}
+
+/// The answer, or the default for an empty line
+fn ask(input: &mut impl BufRead, question: &str, default: &str) -> String {
+    if default.is_empty() {
+        print!("{}: ", question);
+    } else {
+        print!("{} [{}]: ", question, default);
+    }
+    let _ = io::stdout().flush();
+
+    let mut line = String::new();
+    if input.read_line(&mut line).unwrap_or(0) == 0 {
+        // stdin closed, nobody can answer anymore
+        println!();
+        std::process::exit(1);
+    }
+    match line.trim() {
+        "" => default.to_string(),
+        answer => answer.to_string(),
+    }
+}
+
+fn yes(input: &mut impl BufRead, question: &str, default: bool) -> bool {
+    let answer = ask(input, &format!("{}? (y/n)", question), if default { "y" } else { "n" });
+    matches!(answer.to_lowercase().as_str(), "y" | "yes" | "j" | "ja")
+}
@@ -0,0 +1,521 @@
+// Where the KOMSI commands go to.
+//
+// Normally these are the serial ports of the dashboard hardware,
+// but the bridge can write to anything implementing KomsiSink (stdout, files, memory in tests, ...).
+
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use komsi::KomsiCommand;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep, MissedTickBehavior};
+use tokio_serial::{SerialPort, SerialPortBuilderExt, SerialStream};
+use tokio_util::sync::CancellationToken;
+
+use crate::input::KomsiDecoder;
+use crate::serial::{port_exists, Hotplug, LineMode, PortSelector, PortSettings};
+
+pub trait KomsiSink: Send {
+    /// Name used in messages, e.g. the port name
+    fn name(&self) -> String;
+
+    /// Writes KOMSI commands, `frame` ends with the end of line.
+    /// Returns `NotConnected` if the device is not available at the moment.
+    fn send(&mut self, frame: &[u8]) -> io::Result<()>;
+
+    /// Writes the whole state after `reconnected` returned true.
+    /// Sinks with several devices only send it to the new ones.
+    fn send_full_state(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.send(frame)
+    }
+
+    /// KOMSI commands received from the device since the last call.
+    fn receive(&mut self) -> Vec<KomsiCommand> {
+        Vec::new()
+    }
+
+    /// True once after the device was connected again (or for the first time by a sink
+    /// which connects in the background), it only got the init frame and needs the whole state.
+    fn reconnected(&mut self) -> bool {
+        false
+    }
+
+    /// False while the device cannot get frames, e.g. a port which is not open yet
+    fn connected(&self) -> bool {
+        true
+    }
+
+    /// The player entered another vehicle, empty if not in a vehicle anymore.
+    fn vehicle_changed(&mut self, _vehicle_name: &str) {}
+
+    /// TheBus actions requested since the last call, for sinks which do not speak KOMSI
+    /// (e.g. MQTT). KOMSI commands go through `receive` and the input mapping.
+    fn receive_actions(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Flushes and releases the device at shutdown, after the last frame was sent.
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// The task which still writes after `close`, the bridge waits a moment for it.
+    fn closing(&mut self) -> Option<JoinHandle<()>> {
+        None
+    }
+}
+
+/// Settings of a serial port and what all ports share
+#[derive(Debug, Clone, Default)]
+pub struct SerialOptions {
+    /// baudrate, data bits, ... of this port
+    pub settings: PortSettings,
+    /// Sent after the port was opened
+    pub init: Vec<u8>,
+    pub verbose: bool,
+    pub debug_serial: bool,
+    /// USB devices plugged in or removed
+    pub hotplug: Hotplug,
+    /// Stops reading and reconnecting in all ports, the last frames are written until `close`
+    pub shutdown: CancellationToken,
+}
+
+/// A port which is not open is tried again after this time, hot-plug events only make it sooner
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// After a hot-plug event the port is tried for this time, the system needs a moment
+/// until the serial port of a new device shows up
+const HOTPLUG_SETTLE: Duration = Duration::from_secs(3);
+
+/// How often a port task looks at hot-plug events and retries,
+/// frames and received bytes are handled at once
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long DTR or RTS stay off for a reset
+const LINE_PULSE: Duration = Duration::from_millis(100);
+
+async fn try_open_serial_port(
+    selector: &PortSelector,
+    settings: &PortSettings,
+    verbose: bool,
+    report_errors: bool,
+) -> Option<(SerialStream, String)> {
+    let Some(portname) = selector.resolve() else {
+        if report_errors {
+            eprintln!("No serial port found for {}.", selector);
+        }
+        return None;
+    };
+
+    let opened = tokio_serial::new(&portname, settings.baudrate)
+        .data_bits(settings.data_bits)
+        .parity(settings.parity)
+        .flow_control(settings.flow_control)
+        .open_native_async();
+
+    let result = match opened {
+        Ok(mut port) => set_lines(&mut port, settings).await.map(|()| port),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(port) => {
+            if verbose {
+                eprintln!("Port {:?} geöffnet mit {} baud.", portname, settings.baudrate);
+            }
+            Some((port, portname))
+        }
+        Err(e) => {
+            if report_errors {
+                eprintln!("Failed to open serial port {}: {}", portname, e);
+            }
+            None
+        }
+    }
+}
+
+// DTR and RTS as configured, a pulse resets the board
+async fn set_lines(port: &mut SerialStream, settings: &PortSettings) -> tokio_serial::Result<()> {
+    for (mode, is_dtr) in [(settings.dtr, true), (settings.rts, false)] {
+        let mut set = |level: bool| {
+            if is_dtr {
+                port.write_data_terminal_ready(level)
+            } else {
+                port.write_request_to_send(level)
+            }
+        };
+
+        match mode {
+            LineMode::Keep => {}
+            LineMode::On => set(true)?,
+            LineMode::Off => set(false)?,
+            LineMode::Pulse => {
+                set(false)?;
+                sleep(LINE_PULSE).await;
+                set(true)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What the port task tells the sink
+#[derive(Debug, Default)]
+struct PortState {
+    /// the port is open, frames are written
+    connected: AtomicBool,
+    reconnected: AtomicBool,
+    /// name of the open port, shown for USB devices
+    portname: Mutex<Option<String>>,
+}
+
+/// A serial port (USB) with the dashboard hardware.
+///
+/// A task owns the port: it writes the frames it gets through a channel, decodes the commands
+/// sent by the device, releases the port when the device is removed and opens it again when the
+/// device is plugged in. After the shutdown token it only writes the last frames until `close`.
+pub struct SerialSink {
+    selector: PortSelector,
+    state: Arc<PortState>,
+    frames: Option<mpsc::UnboundedSender<Vec<u8>>>,
+    received: mpsc::UnboundedReceiver<KomsiCommand>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl SerialSink {
+    /// Needs a tokio runtime, the port is opened by its task.
+    pub fn open(selector: PortSelector, options: SerialOptions) -> Self {
+        let state = Arc::new(PortState::default());
+        let (frames, frames_rx) = mpsc::unbounded_channel();
+        let (received_tx, received) = mpsc::unbounded_channel();
+
+        let task = tokio::spawn(run_serial_port(
+            selector.clone(),
+            options,
+            frames_rx,
+            received_tx,
+            Arc::clone(&state),
+        ));
+
+        Self {
+            selector,
+            state,
+            frames: Some(frames),
+            received,
+            task: Some(task),
+        }
+    }
+}
+
+impl KomsiSink for SerialSink {
+    fn name(&self) -> String {
+        match (&self.selector, self.state.portname.lock().unwrap().as_ref()) {
+            (PortSelector::Usb(_), Some(portname)) => format!("{} ({})", self.selector, portname),
+            _ => self.selector.to_string(),
+        }
+    }
+
+    /// Only queues the frame, write errors are reported by the task.
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        // the task opens the port again
+        if !self.state.connected.load(Ordering::SeqCst) {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+
+        let Some(frames) = &self.frames else {
+            return Err(io::ErrorKind::NotConnected.into());
+        };
+        frames
+            .send(frame.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+
+    fn receive(&mut self) -> Vec<KomsiCommand> {
+        std::iter::from_fn(|| self.received.try_recv().ok()).collect()
+    }
+
+    fn reconnected(&mut self) -> bool {
+        self.state.reconnected.swap(false, Ordering::SeqCst)
+    }
+
+    fn connected(&self) -> bool {
+        self.state.connected.load(Ordering::SeqCst)
+    }
+
+    /// The task writes the frames still queued and closes the port.
+    fn close(&mut self) -> io::Result<()> {
+        self.frames = None;
+        Ok(())
+    }
+
+    fn closing(&mut self) -> Option<JoinHandle<()>> {
+        self.task.take()
+    }
+}
+
+// The task of a serial port: writes the frames, reads the commands of the device
+// and handles reconnection if needed
+async fn run_serial_port(
+    selector: PortSelector,
+    options: SerialOptions,
+    mut frames: mpsc::UnboundedReceiver<Vec<u8>>,
+    received: mpsc::UnboundedSender<KomsiCommand>,
+    state: Arc<PortState>,
+) {
+    let mut decoder = KomsiDecoder::new();
+    let mut generation = options.hotplug.generation();
+    let mut retry_until = Instant::now();
+    let mut next_retry = Instant::now() + RETRY_INTERVAL;
+    let mut check = interval(CHECK_INTERVAL);
+    check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut buffer = [0u8; 256];
+
+    // the first open is like a reconnection, the bridge may have sent the init frame
+    // and the state before the port was open
+    let mut port = reopen(&selector, &options, &state, true).await;
+
+    loop {
+        tokio::select! {
+            _ = options.shutdown.cancelled() => break,
+            frame = frames.recv() => {
+                let Some(frame) = frame else {
+                    // closed
+                    break;
+                };
+                // frames queued before the port was lost are dropped, the whole state follows
+                if let Some(p) = port.as_mut()
+                    && let Err(e) = AsyncWriteExt::write_all(p, &frame).await
+                {
+                    eprintln!("Error writing to port {}: {}", selector, e);
+                    port = state.lost(&mut decoder, &mut retry_until);
+                }
+            }
+            result = read_port(&mut port, &mut buffer) => match result {
+                Ok(n) if n > 0 => {
+                    if options.debug_serial {
+                        eprintln!("REC [{}]: {}", selector, String::from_utf8_lossy(&buffer[..n]));
+                    }
+                    for cmd in decoder.push(&buffer[..n]) {
+                        // the sink is gone, the task is not needed anymore
+                        if received.send(cmd).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Ok(_) => {
+                    eprintln!("Port {} closed by the device.", selector);
+                    port = state.lost(&mut decoder, &mut retry_until);
+                }
+                Err(e) => {
+                    eprintln!("Error reading from port {}: {}", selector, e);
+                    port = state.lost(&mut decoder, &mut retry_until);
+                }
+            },
+            _ = check.tick() => {
+                let hotplug_event = options.hotplug.generation() != generation;
+                if hotplug_event {
+                    generation = options.hotplug.generation();
+                    retry_until = Instant::now() + HOTPLUG_SETTLE;
+                }
+
+                match port {
+                    // Try to reconnect if port is not available, a hot-plug event only makes it sooner:
+                    // a busy port, a port without USB or a slow device still comes back
+                    None => {
+                        let retry = Instant::now() >= next_retry
+                            || (options.hotplug.is_active() && Instant::now() < retry_until);
+                        if retry {
+                            next_retry = Instant::now() + RETRY_INTERVAL;
+                            port = reopen(&selector, &options, &state, false).await;
+                        }
+                    }
+                    // release the port if its device was removed
+                    Some(_) if hotplug_event => {
+                        let portname = state.portname.lock().unwrap().clone();
+                        if let Some(portname) = portname
+                            && !port_exists(&portname)
+                        {
+                            println!("Port {} removed.", selector);
+                            port = state.lost(&mut decoder, &mut retry_until);
+                        }
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+    }
+
+    // the last frames (e.g. the safe state) until the sink is closed, then the port is released
+    if let Some(mut p) = port {
+        while let Some(frame) = frames.recv().await {
+            if let Err(e) = AsyncWriteExt::write_all(&mut p, &frame).await {
+                eprintln!("Error writing to port {}: {}", selector, e);
+                return;
+            }
+        }
+        let _ = AsyncWriteExt::flush(&mut p).await;
+    }
+    state.connected.store(false, Ordering::SeqCst);
+}
+
+// Reads what the device sent, waits forever without a port
+async fn read_port(port: &mut Option<SerialStream>, buffer: &mut [u8]) -> io::Result<usize> {
+    match port {
+        Some(p) => p.read(buffer).await,
+        None => std::future::pending().await,
+    }
+}
+
+// If (re)connection successful, send SimulatorType:TheBus, the bridge sends the state afterwards
+async fn reopen(
+    selector: &PortSelector,
+    options: &SerialOptions,
+    state: &PortState,
+    report_errors: bool,
+) -> Option<SerialStream> {
+    let (mut port, portname) =
+        try_open_serial_port(selector, &options.settings, options.verbose, report_errors).await?;
+
+    match AsyncWriteExt::write_all(&mut port, &options.init).await {
+        Ok(()) => {
+            state.opened(portname);
+            state.reconnected.store(true, Ordering::SeqCst);
+            Some(port)
+        }
+        Err(e) => {
+            eprintln!("Error writing to port {} after opening: {}", selector, e);
+            None
+        }
+    }
+}
+
+impl PortState {
+    fn opened(&self, portname: String) {
+        *self.portname.lock().unwrap() = Some(portname);
+        self.connected.store(true, Ordering::SeqCst);
+    }
+
+    // the device may come back by itself, it is tried for a while even with hot-plug
+    fn lost(&self, decoder: &mut KomsiDecoder, retry_until: &mut Instant) -> Option<SerialStream> {
+        self.connected.store(false, Ordering::SeqCst);
+        *self.portname.lock().unwrap() = None;
+        *decoder = KomsiDecoder::new();
+        *retry_until = Instant::now() + HOTPLUG_SETTLE;
+        None
+    }
+}
+
+/// Prints the KOMSI commands, one line per frame
+pub struct StdoutSink;
+
+impl KomsiSink for StdoutSink {
+    fn name(&self) -> String {
+        "stdout".to_string()
+    }
+
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        out.write_all(frame)?;
+        out.flush()
+    }
+}
+
+/// Appends the KOMSI commands to a file
+pub struct FileSink {
+    name: String,
+    file: File,
+}
+
+impl FileSink {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Self {
+            name: path.display().to_string(),
+            file,
+        })
+    }
+}
+
+impl KomsiSink for FileSink {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.file.write_all(frame)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+}
+
+/// Collects the KOMSI commands in memory, mostly for tests.
+/// Commands pushed to `input` are returned by `receive` as if a device had sent them,
+/// actions pushed to `actions` are returned by `receive_actions`.
+#[derive(Clone, Default)]
+pub struct MemorySink {
+    pub output: Arc<Mutex<Vec<u8>>>,
+    pub input: Arc<Mutex<Vec<KomsiCommand>>>,
+    pub actions: Arc<Mutex<Vec<String>>>,
+    pub reconnected: Arc<AtomicBool>,
+    /// the last vehicle passed to `vehicle_changed`
+    pub vehicle_name: Arc<Mutex<String>>,
+    /// `close` was called
+    pub closed: Arc<AtomicBool>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything sent so far
+    pub fn output(&self) -> Vec<u8> {
+        self.output.lock().unwrap().clone()
+    }
+
+    /// Acts as if the device was unplugged and plugged in again
+    pub fn reconnect(&self) {
+        self.reconnected.store(true, Ordering::SeqCst);
+    }
+}
+
+impl KomsiSink for MemorySink {
+    fn name(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.output.lock().unwrap().extend_from_slice(frame);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Vec<KomsiCommand> {
+        std::mem::take(&mut *self.input.lock().unwrap())
+    }
+
+    fn reconnected(&mut self) -> bool {
+        self.reconnected.swap(false, Ordering::SeqCst)
+    }
+
+    fn vehicle_changed(&mut self, vehicle_name: &str) {
+        *self.vehicle_name.lock().unwrap() = vehicle_name.to_string();
+    }
+
+    fn receive_actions(&mut self) -> Vec<String> {
+        std::mem::take(&mut *self.actions.lock().unwrap())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.closed.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+// Where the telemetry data comes from.
+//
+// Normally this is the telemetry API of TheBus, but the bridge can be fed by anything
+// that delivers the same JSON (replay files, mocks in tests, ...).
+
+use std::future::Future;
+
+use serde_json::Value;
+use the_bus_telemetry::api::RequestConfig;
+
+pub use crate::error::TelemetryError as SourceError;
+
+pub trait TelemetrySource {
+    /// Name of the vehicle the player sits in, empty if not in a bus.
+    fn current_vehicle_name(&mut self) -> impl Future<Output = Result<String, SourceError>> + Send;
+
+    /// JSON of the vehicle as delivered by `/vehicles/<name>`.
+    fn vehicle(&mut self, name: &str) -> impl Future<Output = Result<Value, SourceError>> + Send;
+
+    /// JSON of the world as delivered by `/world`.
+    fn world(&mut self) -> impl Future<Output = Result<Value, SourceError>> + Send;
+
+    /// Triggers a button action of the vehicle.
+    fn send_action(
+        &mut self,
+        name: &str,
+        action: &str,
+    ) -> impl Future<Output = Result<(), SourceError>> + Send;
+
+    /// True if the source has no more data, e.g. at the end of a replay.
+    fn finished(&self) -> bool {
+        false
+    }
+}
+
+/// The telemetry API of TheBus
+pub struct HttpSource {
+    pub config: RequestConfig,
+    client: reqwest::Client,
+}
+
+impl HttpSource {
+    pub fn new(config: RequestConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// GET of an API path, the errors tell what went wrong
+    async fn get(&self, path: &str) -> Result<Value, SourceError> {
+        let url = format!("http://{}:{}/{}", self.config.host, self.config.port, path);
+        if self.config.debugging {
+            println!("GET {}", url);
+        }
+
+        let response = self
+            .client
+            .get(url)
+            .timeout(self.config.timeout)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| SourceError::from_reqwest(e, path))?;
+
+        response
+            .json::<Value>()
+            .await
+            .map_err(|e| SourceError::from_reqwest(e, path))
+    }
+}
+
+impl TelemetrySource for HttpSource {
+    async fn current_vehicle_name(&mut self) -> Result<String, SourceError> {
+        let player = self.get("player").await?;
+        if self.config.debugging {
+            println!("player: {:?}", player);
+        }
+
+        // walking around or in the menu
+        if player.get("Mode").and_then(Value::as_str) != Some("Vehicle") {
+            return Ok(String::new());
+        }
+
+        Ok(player
+            .get("CurrentVehicle")
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .to_string())
+    }
+
+    async fn vehicle(&mut self, name: &str) -> Result<Value, SourceError> {
+        self.config.vehicle_name = name.to_string();
+        let path = format!("vehicles/{}", name);
+        match self.get(&path).await {
+            Err(SourceError::HttpStatus(404, _)) => Err(SourceError::VehicleNotFound(name.to_string())),
+            result => result,
+        }
+    }
+
+    async fn world(&mut self) -> Result<Value, SourceError> {
+        self.get("world").await
+    }
+
+    /// we press and release like a click on the button in the cockpit
+    async fn send_action(&mut self, name: &str, action: &str) -> Result<(), SourceError> {
+        self.config.vehicle_name = name.to_string();
+        for event in ["sendeventpress", "sendeventrelease"] {
+            let path = format!("vehicles/{}/{}", name, event);
+            let mut url = reqwest::Url::parse(&format!("http://{}:{}/{}", self.config.host, self.config.port, path))
+                .map_err(|e| SourceError::Other(format!("{}: {}", path, e)))?;
+            // action names come from the config, they may contain anything
+            url.query_pairs_mut().append_pair("event", action);
+            if self.config.debugging {
+                println!("GET {}", url);
+            }
+
+            // the answer is not JSON, only the status matters
+            self.client
+                .get(url)
+                .timeout(self.config.timeout)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| SourceError::from_reqwest(e, &path))?;
+        }
+        Ok(())
+    }
+}
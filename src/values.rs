@@ -0,0 +1,132 @@
+// The KOMSI state as named values, for consumers which are not KOMSI devices
+// (web pages, MQTT, ...). It is built from the same frames the devices get.
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Map, Value};
+
+/// Name of the value of a KOMSI code, like the fields of `VehicleState`
+pub fn code_name(code: char) -> Option<&'static str> {
+    Some(match code {
+        'A' => "ignition",
+        'B' => "engine",
+        'C' => "doors",
+        'D' => "indicator",
+        'E' => "fixing_brake",
+        'F' => "lights_warning",
+        'G' => "lights_main",
+        'H' => "lights_front_door",
+        'I' => "lights_second_door",
+        'J' => "lights_third_door",
+        'K' => "lights_stop_request",
+        'L' => "lights_stop_brake",
+        'M' => "lights_high_beam",
+        'N' => "battery_light",
+        'O' => "simulator_type",
+        'P' => "door_clearance",
+        'd' => "debug_mode",
+        'i' => "info_request",
+        'o' => "total_distance",
+        'p' => "protocol_switch",
+        'r' => "datetime",
+        's' => "maxspeed",
+        't' => "rpm",
+        'u' => "pressure",
+        'v' => "temperature",
+        'w' => "oil",
+        'x' => "fuel",
+        'y' => "speed",
+        'z' => "water",
+        _ => return None,
+    })
+}
+
+/// Splits a frame like `A1y50\n` into its commands: (code, digits)
+pub fn split_frame(frame: &[u8]) -> Vec<(char, String)> {
+    let mut commands: Vec<(char, String)> = Vec::new();
+
+    for &b in frame {
+        if b.is_ascii_alphabetic() {
+            commands.push((b as char, String::new()));
+        } else if b.is_ascii_digit()
+            && let Some((_, digits)) = commands.last_mut()
+        {
+            digits.push(b as char);
+        }
+    }
+
+    commands
+}
+
+/// The value of a command as JSON: lamps and switches are booleans,
+/// the date time is `2026-01-01T09:43:48`, all others are numbers.
+pub fn json_value(code: char, digits: &str) -> Value {
+    match code {
+        'A'..='C' | 'E'..='N' | 'P' => Value::Bool(digits != "0"),
+        'r' if digits.len() == 14 => Value::String(format!(
+            "{}-{}-{}T{}:{}:{}",
+            &digits[0..4],
+            &digits[4..6],
+            &digits[6..8],
+            &digits[8..10],
+            &digits[10..12],
+            &digits[12..14]
+        )),
+        _ => digits
+            .parse::<u64>()
+            .map(Value::from)
+            .unwrap_or_else(|_| Value::String(digits.to_string())),
+    }
+}
+
+/// The last value of every KOMSI code
+#[derive(Debug, Clone, Default)]
+pub struct KomsiValues {
+    values: BTreeMap<char, String>,
+}
+
+impl KomsiValues {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes the commands of a frame, returns the changed values.
+    pub fn apply_frame(&mut self, frame: &[u8]) -> Vec<(char, String)> {
+        let mut changes = Vec::new();
+
+        for (code, digits) in split_frame(frame) {
+            if self.values.get(&code) != Some(&digits) {
+                self.values.insert(code, digits.clone());
+                changes.push((code, digits));
+            }
+        }
+
+        changes
+    }
+
+    pub fn get(&self, code: char) -> Option<&str> {
+        self.values.get(&code).map(String::as_str)
+    }
+
+    /// All values, sorted by code
+    pub fn all(&self) -> Vec<(char, String)> {
+        self.values.iter().map(|(&code, digits)| (code, digits.clone())).collect()
+    }
+
+    /// All values as a JSON object keyed by name
+    pub fn to_json(&self) -> Value {
+        to_json(self.values.iter().map(|(&code, digits)| (code, digits.as_str())))
+    }
+}
+
+/// Values as a JSON object keyed by name, unknown codes are keyed by the code
+pub fn to_json<'a>(values: impl IntoIterator<Item = (char, &'a str)>) -> Value {
+    let map: Map<String, Value> = values
+        .into_iter()
+        .map(|(code, digits)| {
+            let name = code_name(code).map_or_else(|| code.to_string(), str::to_string);
+            (name, json_value(code, digits))
+        })
+        .collect();
+    json!(map)
+}
@@ -0,0 +1,267 @@
+// The vehicle state as JSON for browser dashboards, tablets and OBS overlays.
+//
+// [web]
+// listen = 127.0.0.1:8080
+//
+// - GET /state       the whole state
+// - WebSocket /ws    the whole state after connecting, then every change
+// - GET /            a page showing the WebSocket messages
+//
+// The state is built from the KOMSI commands, so a web dashboard shows
+// exactly what the hardware dashboards get.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::sink::KomsiSink;
+use crate::values::{to_json, KomsiValues};
+
+/// Name of the config section of the web server
+pub const WEB_SECTION: &str = "web";
+
+/// Messages a slow client may fall behind before it gets the whole state again
+const CHANNEL_CAPACITY: usize = 64;
+
+/// Longest request header which is accepted
+const MAX_HEADER: usize = 8192;
+
+/// How long a client may take to send its request
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+const INDEX_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>TheBus2Komsi</title></head>
+<body>
+<pre id="state">connecting...</pre>
+<script>
+const ws = new WebSocket("ws://" + location.host + "/ws");
+ws.onmessage = (e) => {
+  document.getElementById("state").textContent = JSON.stringify(JSON.parse(e.data).state, null, 2);
+};
+ws.onclose = () => { document.getElementById("state").textContent = "disconnected"; };
+</script>
+</body>
+</html>
+"#;
+
+/// `{"type":"state","state":{...}}`
+pub fn state_message(values: &KomsiValues) -> String {
+    json!({ "type": "state", "state": values.to_json() }).to_string()
+}
+
+/// `{"type":"diff","changes":{...},"state":{...}}`
+pub fn diff_message(changes: &[(char, String)], values: &KomsiValues) -> String {
+    let changes = to_json(changes.iter().map(|(code, digits)| (*code, digits.as_str())));
+    json!({ "type": "diff", "changes": changes, "state": values.to_json() }).to_string()
+}
+
+/// Publishes the KOMSI commands as JSON over HTTP and WebSocket
+pub struct WebSink {
+    address: SocketAddr,
+    values: Arc<Mutex<KomsiValues>>,
+    updates: broadcast::Sender<String>,
+    task: JoinHandle<()>,
+}
+
+impl WebSink {
+    /// Starts the server, e.g. on `127.0.0.1:8080`. Port 0 picks a free port.
+    pub async fn bind(address: &str, verbose: bool) -> io::Result<Self> {
+        let listener = TcpListener::bind(address).await?;
+        let address = listener.local_addr()?;
+        let values = Arc::new(Mutex::new(KomsiValues::new()));
+        let (updates, _) = broadcast::channel(CHANNEL_CAPACITY);
+
+        if verbose {
+            println!("Web server on http://{}/", address);
+        }
+
+        let task = tokio::spawn(serve(listener, Arc::clone(&values), updates.clone(), verbose));
+
+        Ok(Self {
+            address,
+            values,
+            updates,
+            task,
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for WebSink {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl KomsiSink for WebSink {
+    fn name(&self) -> String {
+        format!("web {}", self.address)
+    }
+
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        let mut values = self.values.lock().unwrap();
+        let changes = values.apply_frame(frame);
+        if !changes.is_empty() {
+            // no receivers is not an error, nobody is watching
+            let _ = self.updates.send(diff_message(&changes, &values));
+        }
+        Ok(())
+    }
+
+    /// Stops listening, open pages keep the last state.
+    fn close(&mut self) -> io::Result<()> {
+        self.task.abort();
+        Ok(())
+    }
+}
+
+async fn serve(
+    listener: TcpListener,
+    values: Arc<Mutex<KomsiValues>>,
+    updates: broadcast::Sender<String>,
+    verbose: bool,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Web server: {}", e);
+                continue;
+            }
+        };
+
+        let values = Arc::clone(&values);
+        let updates = updates.subscribe();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, values, updates).await
+                && verbose
+            {
+                eprintln!("Web client {}: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    values: Arc<Mutex<KomsiValues>>,
+    updates: broadcast::Receiver<String>,
+) -> io::Result<()> {
+    // look at the header without taking it, the WebSocket handshake reads it again
+    let mut buf = vec![0u8; MAX_HEADER];
+    let peek = async {
+        loop {
+            let n = stream.peek(&mut buf).await?;
+            if n == 0 {
+                return Ok(None);
+            }
+            let header = String::from_utf8_lossy(&buf[..n]).to_string();
+            if header.contains("\r\n\r\n") || n == MAX_HEADER {
+                return Ok(Some(header));
+            }
+            // the rest of the header is on the way
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    // a client which sends nothing is dropped
+    let header = match timeout(HEADER_TIMEOUT, peek).await {
+        Ok(Ok(Some(header))) => header,
+        Ok(Ok(None)) => return Ok(()),
+        Ok(Err(e)) => return Err(e),
+        Err(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "no request")),
+    };
+
+    let path = header
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or("/")
+        .to_string();
+    let is_websocket = header
+        .lines()
+        .any(|line| line.to_ascii_lowercase().replace(' ', "") == "upgrade:websocket");
+
+    if is_websocket {
+        serve_websocket(stream, values, updates).await
+    } else {
+        serve_http(stream, &path, &values).await
+    }
+}
+
+async fn serve_http(
+    mut stream: TcpStream,
+    path: &str,
+    values: &Arc<Mutex<KomsiValues>>,
+) -> io::Result<()> {
+    // take the request which was only peeked
+    let mut buf = vec![0u8; MAX_HEADER];
+    let _ = stream.read(&mut buf).await?;
+
+    let (status, content_type, body) = match path.split('?').next().unwrap_or(path) {
+        "/" => ("200 OK", "text/html; charset=utf-8", INDEX_HTML.to_string()),
+        "/state" => {
+            let state: Value = values.lock().unwrap().to_json();
+            ("200 OK", "application/json", state.to_string())
+        }
+        _ => ("404 Not Found", "text/plain", "not found".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+async fn serve_websocket(
+    stream: TcpStream,
+    values: Arc<Mutex<KomsiValues>>,
+    mut updates: broadcast::Receiver<String>,
+) -> io::Result<()> {
+    let ws = tokio_tungstenite::accept_async(stream)
+        .await
+        .map_err(io::Error::other)?;
+    let (mut write, mut read) = ws.split();
+
+    // updates after this snapshot are already queued in `updates`
+    let state = state_message(&values.lock().unwrap());
+    write.send(Message::text(state)).await.map_err(io::Error::other)?;
+
+    loop {
+        tokio::select! {
+            update = updates.recv() => {
+                let message = match update {
+                    Ok(message) => message,
+                    // the client was too slow, it starts over with the whole state
+                    Err(broadcast::error::RecvError::Lagged(_)) => state_message(&values.lock().unwrap()),
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                };
+                write.send(Message::text(message)).await.map_err(io::Error::other)?;
+            }
+            incoming = read.next() => match incoming {
+                // pings are answered by tungstenite, everything else is ignored
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(io::Error::other(e)),
+            }
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use std::fs;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use komsi::KomsiCommand;
+use serde_json::{json, Value};
+use the_bus_2_komsi::bridge::{init_frame, safe_state_frame, Bridge, BridgeOptions, Reload};
+use the_bus_2_komsi::input::InputMap;
+use the_bus_2_komsi::mapping::MappingEntry;
+use the_bus_2_komsi::profile::Profiles;
+use the_bus_2_komsi::schedule::Pacer;
+use the_bus_2_komsi::sink::{KomsiSink, MemorySink};
+use the_bus_2_komsi::source::{SourceError, TelemetrySource};
+
+struct MockSource {
+    vehicle: Value,
+    actions: Arc<Mutex<Vec<String>>>,
+}
+
+impl TelemetrySource for MockSource {
+    async fn current_vehicle_name(&mut self) -> Result<String, SourceError> {
+        Ok(self.vehicle["ActorName"].as_str().unwrap().to_string())
+    }
+
+    async fn vehicle(&mut self, _name: &str) -> Result<Value, SourceError> {
+        Ok(self.vehicle.clone())
+    }
+
+    async fn world(&mut self) -> Result<Value, SourceError> {
+        Ok(json!({
+            "LevelName": "Berlin",
+            "DateTime": "2026-01-01T09:43:48",
+            "TimeFactor": 1.0,
+            "BaseLatitude": 52.5,
+            "BaseLongitude": 13.4
+        }))
+    }
+
+    async fn send_action(&mut self, _name: &str, action: &str) -> Result<(), SourceError> {
+        self.actions.lock().unwrap().push(action.to_string());
+        Ok(())
+    }
+}
+
+/// TheBus is not running
+struct DownSource;
+
+impl TelemetrySource for DownSource {
+    async fn current_vehicle_name(&mut self) -> Result<String, SourceError> {
+        Err(SourceError::ConnectionRefused("/vehicles".to_string()))
+    }
+
+    async fn vehicle(&mut self, name: &str) -> Result<Value, SourceError> {
+        Err(SourceError::ConnectionRefused(name.to_string()))
+    }
+
+    async fn world(&mut self) -> Result<Value, SourceError> {
+        Err(SourceError::ConnectionRefused("/world".to_string()))
+    }
+
+    async fn send_action(&mut self, name: &str, _action: &str) -> Result<(), SourceError> {
+        Err(SourceError::ConnectionRefused(name.to_string()))
+    }
+}
+
+fn mock_source() -> MockSource {
+    let json = fs::read_to_string("tests/json/scania_citywide.json").unwrap();
+    MockSource {
+        vehicle: serde_json::from_str(&json).unwrap(),
+        actions: Arc::new(Mutex::new(Vec::new())),
+    }
+}
+
+#[tokio::test]
+async fn test_bridge_sends_changes_to_sinks() {
+    let sink = MemorySink::new();
+    let mut bridge = Bridge::new(mock_source())
+        .options(BridgeOptions::default())
+        .sink(Box::new(sink.clone()));
+
+    bridge.send_to_sinks(&init_frame());
+    bridge.step().await;
+
+    let output = String::from_utf8(sink.output()).unwrap();
+    assert!(output.starts_with("O1\n"));
+    assert!(output.contains("r20260101094348"));
+    assert!(output.ends_with('\n'));
+
+    // nothing changed, nothing sent
+    let len = sink.output().len();
+    bridge.step().await;
+    assert_eq!(sink.output().len(), len);
+}
+
+#[tokio::test]
+async fn test_bridge_sends_actions_from_sinks() {
+    let source = mock_source();
+    let actions = Arc::clone(&source.actions);
+
+    let mut input_map = InputMap::new();
+    input_map.insert(KomsiCommand::FrontDoor(true), "DoorFrontOpen".to_string());
+
+    let sink = MemorySink::new();
+    let mut bridge = Bridge::new(source)
+        .input_map(input_map)
+        .sink(Box::new(sink.clone()));
+
+    sink.input.lock().unwrap().push(KomsiCommand::FrontDoor(true));
+    sink.input.lock().unwrap().push(KomsiCommand::FrontDoor(false));
+    bridge.step().await;
+
+    assert_eq!(*actions.lock().unwrap(), vec!["DoorFrontOpen".to_string()]);
+}
+
+#[tokio::test]
+async fn test_bridge_tells_sinks_the_vehicle_and_takes_their_actions() {
+    let source = mock_source();
+    let actions = Arc::clone(&source.actions);
+    let vehicle_name = source.vehicle["ActorName"].as_str().unwrap().to_string();
+
+    let sink = MemorySink::new();
+    let mut bridge = Bridge::new(source).sink(Box::new(sink.clone()));
+
+    // e.g. from MQTT, no input mapping needed
+    sink.actions.lock().unwrap().push("BusStopBrake".to_string());
+    bridge.step().await;
+
+    assert_eq!(*sink.vehicle_name.lock().unwrap(), vehicle_name);
+    assert_eq!(*actions.lock().unwrap(), vec!["BusStopBrake".to_string()]);
+}
+
+#[tokio::test]
+async fn test_bridge_refreshes_reconnected_sink() {
+    let mut profiles = Profiles::new();
+    profiles
+        .base
+        .insert(MappingEntry::parse("t", "RPM").unwrap());
+
+    let gauges = MemorySink::new();
+    let lamps = MemorySink::new();
+    let mut bridge = Bridge::new(mock_source())
+        .profiles(profiles)
+        .sink(Box::new(gauges.clone()))
+        .sink(Box::new(lamps.clone()));
+
+    bridge.step().await;
+    let gauges_len = gauges.output().len();
+    let lamps_len = lamps.output().len();
+
+    lamps.reconnect();
+    bridge.step().await;
+    let refresh = lamps.output()[lamps_len..].to_vec();
+    assert_eq!(refresh, bridge.full_state_frame());
+
+    let refresh = String::from_utf8(refresh).unwrap();
+    assert!(refresh.starts_with("A1"));
+    assert!(refresh.contains("r20260101094348"));
+    assert!(refresh.contains('t'));
+    assert!(refresh.ends_with('\n'));
+
+    // the other sink is not bothered
+    assert_eq!(gauges.output().len(), gauges_len);
+
+    // only once
+    bridge.step().await;
+    assert_eq!(lamps.output().len(), lamps_len + refresh.len());
+}
+
+#[tokio::test]
+async fn test_bridge_waiting_for_bus_is_not_late() {
+    let mut source = mock_source();
+    source.vehicle["ActorName"] = json!("");
+
+    let options = BridgeOptions::default()
+        .interval(Duration::from_millis(10))
+        .error_interval(Duration::from_millis(50));
+    let mut pacer = Pacer::new(options.interval);
+    let mut bridge = Bridge::new(source).options(options);
+
+    // each poll waits longer than the interval for a bus
+    bridge.poll(&mut pacer).await;
+    bridge.poll(&mut pacer).await;
+    assert_eq!(pacer.overrun_report(), None);
+}
+
+#[tokio::test]
+async fn test_bridge_waiting_after_error_is_not_late() {
+    let options = BridgeOptions::default()
+        .interval(Duration::from_millis(10))
+        .error_interval(Duration::from_millis(50));
+    let mut pacer = Pacer::new(options.interval);
+    let mut bridge = Bridge::new(DownSource).options(options);
+
+    // each poll waits longer than the interval for TheBus
+    bridge.poll(&mut pacer).await;
+    bridge.poll(&mut pacer).await;
+    assert_eq!(pacer.overrun_report(), None);
+}
+
+#[tokio::test]
+async fn test_bridge_sends_safe_state_on_shutdown() {
+    assert_eq!(safe_state_frame("A0 y0").unwrap(), b"A0y0\n");
+    assert!(safe_state_frame("A0Q9").is_err());
+    assert!(safe_state_frame("").is_err());
+
+    let sink = MemorySink::new();
+    let mut bridge = Bridge::new(mock_source())
+        .options(BridgeOptions::default().safe_state(Some(safe_state_frame("A0y0").unwrap())))
+        .sink(Box::new(sink.clone()));
+
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let run = tokio::spawn(async move {
+        bridge
+            .run_until(async {
+                let _ = stopped.await;
+            })
+            .await;
+        bridge
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    stop.send(()).unwrap();
+
+    let mut bridge = run.await.unwrap();
+
+    let output = String::from_utf8(sink.output()).unwrap();
+    assert!(output.starts_with("O1\n"));
+    assert!(output.contains("A1"));
+    assert!(output.ends_with("A0y0\n"));
+
+    // the safe state is out before the sinks are closed
+    assert!(!sink.closed.load(Ordering::SeqCst));
+    let summary = bridge.close().await;
+    assert!(sink.closed.load(Ordering::SeqCst));
+    assert!(summary.polls >= 1);
+    assert_eq!(summary.api_errors, 0);
+    // init, the vehicle and the safe state
+    assert_eq!(summary.frames_sent, 3);
+    assert!(summary.to_string().starts_with("Ran 0:00:00, "));
+}
+
+#[tokio::test]
+async fn test_bridge_reload_replaces_changed_sinks() {
+    let kept = MemorySink::new();
+    let changed = MemorySink::new();
+    let mut bridge = Bridge::new(mock_source())
+        .keyed_sink("port.gauges", Box::new(kept.clone()))
+        .keyed_sink("port.lamps", Box::new(changed.clone()));
+    bridge.step().await;
+    let kept_len = kept.output().len();
+
+    let mut profiles = Profiles::new();
+    profiles
+        .base
+        .insert(MappingEntry::parse("t", "RPM").unwrap());
+    let reopened = MemorySink::new();
+    let sink: Box<dyn KomsiSink> = Box::new(reopened.clone());
+    bridge
+        .reload(Reload {
+            options: BridgeOptions::default(),
+            input_map: InputMap::new(),
+            profiles,
+            close: vec!["port.lamps".to_string()],
+            open: Box::pin(async move { vec![("port.lamps".to_string(), sink)] }),
+        })
+        .await;
+    assert!(changed.closed.load(Ordering::SeqCst));
+    assert!(!kept.closed.load(Ordering::SeqCst));
+
+    // the new mapping applies with the next poll, everything is sent again
+    bridge.step().await;
+    let output = String::from_utf8(reopened.output()).unwrap();
+    assert!(output.starts_with("O1\n"));
+    assert!(output.contains("A1"));
+    assert!(output.contains('t'));
+    let kept_output = String::from_utf8(kept.output()[kept_len..].to_vec()).unwrap();
+    assert!(kept_output.contains('t'));
+    assert!(kept_output.contains("A1"));
+}
+
+/// The port opens after the bridge sent the init frame and the first state,
+/// the device still gets both without being plugged in again.
+#[cfg(unix)]
+#[tokio::test]
+async fn test_bridge_sends_init_and_state_to_port_opened_late() {
+    use std::time::Duration;
+
+    use the_bus_2_komsi::serial::PortSelector;
+    use the_bus_2_komsi::sink::{SerialOptions, SerialSink};
+    use tokio::io::AsyncReadExt;
+    use tokio_serial::{SerialPort, SerialStream};
+
+    let (mut device, port) = SerialStream::pair().unwrap();
+    let portname = port.name().unwrap();
+    drop(port);
+
+    let sink = SerialSink::open(
+        PortSelector::Name(portname),
+        SerialOptions {
+            init: init_frame(),
+            ..Default::default()
+        },
+    );
+    let mut bridge = Bridge::new(mock_source())
+        .options(BridgeOptions::default())
+        .sink(Box::new(sink));
+
+    // the task of the port did not run yet
+    bridge.send_to_sinks(&init_frame());
+    bridge.step().await;
+
+    let mut output = Vec::new();
+    let mut buffer = [0u8; 1024];
+    for _ in 0..100 {
+        bridge.step().await;
+        if let Ok(Ok(n)) = tokio::time::timeout(Duration::from_millis(20), device.read(&mut buffer)).await {
+            output.extend_from_slice(&buffer[..n]);
+        }
+        if output.ends_with(b"\n") && String::from_utf8_lossy(&output).contains("r20260101094348") {
+            break;
+        }
+    }
+
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.starts_with("O1\n"), "{:?}", output);
+    assert!(output.contains("r20260101094348"), "{:?}", output);
+}
@@ -0,0 +1,66 @@
+use std::fs;
+
+use the_bus_2_komsi::check::{ConfigCheck, IniLines};
+
+#[test]
+fn test_check_config() {
+    let path = std::env::temp_dir().join(format!("thebus2komsi_check_{}.ini", std::process::id()));
+    fs::write(
+        &path,
+        "[default]
+portname = com8
+portname2 = com8
+baudrate = -1
+sleeptime = 200
+sleeptime = 100
+portnmae3 = com9
+
+[port.lamps]
+device = com10
+parity = maybe
+
+[input]
+X1 = DoorFrontOpen
+
+[wifi]
+tcp = 192.168.1.50:5000
+",
+    )
+    .unwrap();
+    let check = ConfigCheck::read(&path);
+    fs::remove_file(&path).unwrap();
+
+    let mut findings: Vec<(Option<usize>, &str)> = check
+        .findings
+        .iter()
+        .map(|f| (f.line, f.message.as_str()))
+        .collect();
+    findings.sort();
+
+    let expected = [
+        (3, "portname2 is the same port as portname"),
+        (4, "baudrate = -1 ignored"),
+        (6, "[default] sleeptime is already set in line 5"),
+        (7, "unknown key portnmae3 in [default]"),
+        // the line of the bad value, not of the section
+        (11, "[port.lamps] ignored: maybe is not"),
+        (14, "Input mapping X1 is not a KOMSI command"),
+        (16, "unknown section [wifi]"),
+    ];
+    assert_eq!(findings.len(), expected.len(), "{:?}", findings);
+    for ((line, message), (expected_line, start)) in findings.iter().zip(expected) {
+        assert_eq!(*line, Some(expected_line));
+        assert!(message.starts_with(start), "{}", message);
+    }
+    assert!(!check.is_ok());
+}
+
+#[test]
+fn test_ini_lines() {
+    let lines = IniLines::parse("ip = 127.0.0.1\n; comment\n[Input]\nD1 = IndicatorLeft\n");
+    assert_eq!(lines.key("default", "ip"), Some(1));
+    assert_eq!(lines.section("input"), Some(3));
+    // KOMSI codes are case sensitive
+    assert_eq!(lines.key("input", "D1"), Some(4));
+    assert_eq!(lines.key("input", "d1"), None);
+}
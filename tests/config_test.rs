@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+use the_bus_2_komsi::config::{
+    find_config_file, Config, ConfigWatcher, Overrides, Source, ENV_BAUDRATE, ENV_PORTS, ENV_SLEEPTIME,
+};
+use the_bus_2_komsi::serial::PortSelector;
+
+#[test]
+fn test_find_config_file() {
+    let path = std::env::temp_dir().join(format!("thebus2komsi_find_{}.ini", std::process::id()));
+    fs::write(&path, "[default]\nportname = COM8\n").unwrap();
+
+    assert_eq!(find_config_file(Some(&path)).unwrap(), Some(path.clone()));
+    fs::remove_file(&path).unwrap();
+
+    // a file given on the command line must be there
+    assert!(find_config_file(Some(&path)).is_err());
+}
+
+#[test]
+fn test_overrides() {
+    let path = std::env::temp_dir().join(format!("thebus2komsi_overrides_{}.ini", std::process::id()));
+    fs::write(
+        &path,
+        "[default]\nportname = COM8\nbaudrate = 57600\nip = 192.168.1.2\n[port.lamps]\ndevice = COM9\n",
+    )
+    .unwrap();
+    let mut config = Config::load(&path, false);
+    fs::remove_file(&path).unwrap();
+
+    let env = HashMap::from([
+        (ENV_PORTS, "COM3, 2341:0043"),
+        (ENV_BAUDRATE, "fast"),
+        (ENV_SLEEPTIME, "100"),
+    ]);
+    let mut overrides = Overrides::from_env(|var| env.get(var).map(|v| v.to_string()));
+    assert!(overrides.ip.is_none());
+    // not a number
+    assert!(overrides.baudrate.is_none());
+    overrides.sleeptime = Some((50, Source::CommandLine));
+
+    config.apply_overrides(overrides);
+
+    assert_eq!(config.ip, "192.168.1.2");
+    assert_eq!(config.sources["ip"], Source::File(path.clone()));
+    assert_eq!(config.baudrate, 57600);
+    assert_eq!(config.sleeptime, 50);
+    assert_eq!(config.sources["sleeptime"], Source::CommandLine);
+
+    assert_eq!(config.ports.len(), 2);
+    assert_eq!(config.ports[0].selector, PortSelector::Name("COM3".to_string()));
+    assert!(matches!(config.ports[1].selector, PortSelector::Usb(_)));
+    assert_eq!(config.ports[1].settings.baudrate, 57600);
+    assert_eq!(config.sources["ports"], Source::Env(ENV_PORTS));
+
+    config.apply_overrides(Overrides {
+        baudrate: Some((9600, Source::CommandLine)),
+        ..Default::default()
+    });
+    assert!(config.ports.iter().all(|p| p.settings.baudrate == 9600));
+
+    let report = config.report();
+    assert!(report.contains("ports = COM3, usb 2341:0043 (environment THEBUS2KOMSI_PORTS)"));
+    assert!(report.contains("baudrate = 9600 (command line)"));
+
+    let config = Config::default();
+    assert!(config.report().contains("ip = 127.0.0.1 (default)"));
+}
+
+#[test]
+fn test_config_watcher() {
+    let path = std::env::temp_dir().join(format!("thebus2komsi_watch_{}.ini", std::process::id()));
+    fs::write(&path, "[default]\nsleeptime = 200\n").unwrap();
+    let mut watcher = ConfigWatcher::new(&path);
+    assert!(!watcher.changed());
+
+    // the modification time is set, the file system may be too coarse for two quick writes
+    fs::write(&path, "[default]\nbaudrate = fast\n").unwrap();
+    let file = fs::File::options().write(true).open(&path).unwrap();
+    file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+    drop(file);
+    assert!(watcher.changed());
+    assert!(!watcher.changed());
+
+    // an invalid edit is not used
+    let config = Config::read(&path, false);
+    assert_eq!(config.problems.len(), 1);
+
+    // a file being replaced is no change
+    fs::remove_file(&path).unwrap();
+    assert!(!watcher.changed());
+}
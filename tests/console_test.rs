@@ -0,0 +1,62 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use komsi::KomsiCommand;
+use the_bus_2_komsi::console::{console_frame, read_script, ConsoleLine, Decoded};
+
+#[test]
+fn test_console_line() {
+    assert_eq!(
+        ConsoleLine::parse("A1 y57 D2").unwrap(),
+        ConsoleLine::Send(vec![
+            KomsiCommand::Ignition(true),
+            KomsiCommand::Speed(57),
+            KomsiCommand::Indicator(2),
+        ])
+    );
+    assert_eq!(ConsoleLine::parse("  ").unwrap(), ConsoleLine::Empty);
+    assert_eq!(ConsoleLine::parse("# lamps").unwrap(), ConsoleLine::Empty);
+    assert_eq!(
+        ConsoleLine::parse(":wait 250").unwrap(),
+        ConsoleLine::Wait(Duration::from_millis(250))
+    );
+    assert_eq!(
+        ConsoleLine::parse(":run lamps.txt").unwrap(),
+        ConsoleLine::Run(PathBuf::from("lamps.txt"))
+    );
+    assert_eq!(ConsoleLine::parse("!3").unwrap(), ConsoleLine::Repeat(3));
+    assert_eq!(ConsoleLine::parse(":quit").unwrap(), ConsoleLine::Quit);
+
+    // nothing is sent if a part is wrong
+    assert!(ConsoleLine::parse("A1 y").is_err());
+    assert!(ConsoleLine::parse("A1 Q1").is_err());
+    assert!(ConsoleLine::parse("A1, y57").is_err());
+    assert!(ConsoleLine::parse(":wait soon").is_err());
+    assert!(ConsoleLine::parse("!0").is_err());
+    assert!(ConsoleLine::parse(":send A1").is_err());
+
+    let frame = console_frame(&[KomsiCommand::Ignition(true), KomsiCommand::Speed(57)]);
+    assert_eq!(frame, b"A1y57\n");
+    assert_eq!(Decoded(&KomsiCommand::FrontDoor(true)).to_string(), "H1 FrontDoor(true)");
+}
+
+#[test]
+fn test_console_script() {
+    let path = std::env::temp_dir().join(format!("thebus2komsi_script_{}.txt", std::process::id()));
+    fs::write(&path, "# all lamps\nA1 B1\n:wait 500\n\nA0 B0\n").unwrap();
+    assert_eq!(
+        read_script(&path).unwrap(),
+        vec![
+            ConsoleLine::Send(vec![KomsiCommand::Ignition(true), KomsiCommand::Engine(true)]),
+            ConsoleLine::Wait(Duration::from_millis(500)),
+            ConsoleLine::Send(vec![KomsiCommand::Ignition(false), KomsiCommand::Engine(false)]),
+        ]
+    );
+
+    // the error tells the line
+    fs::write(&path, "A1\n:run other.txt\n").unwrap();
+    let error = read_script(&path).unwrap_err();
+    fs::remove_file(&path).unwrap();
+    assert!(error.ends_with(":2: only KOMSI commands and :wait can be used in a script"), "{}", error);
+}
@@ -0,0 +1,107 @@
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::time::Duration;
+
+use the_bus_2_komsi::bridge::{Bridge, BridgeOptions};
+use the_bus_2_komsi::error::TelemetryError;
+use the_bus_2_komsi::mockapi::{MockApi, MockServer};
+use the_bus_2_komsi::sink::MemorySink;
+use the_bus_2_komsi::source::{HttpSource, TelemetrySource};
+use the_bus_telemetry::api::RequestConfig;
+
+fn source(port: u16, timeout: Duration) -> HttpSource {
+    HttpSource::new(
+        RequestConfig::new()
+            .port(port.to_string())
+            .timeout(timeout),
+    )
+}
+
+/// A port nobody listens on
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+#[test]
+fn test_error_record_and_backoff() {
+    let errors = [
+        TelemetryError::ConnectionRefused("player: connection refused".to_string()),
+        TelemetryError::Timeout("world".to_string()),
+        TelemetryError::HttpStatus(500, "vehicles/Bus".to_string()),
+        TelemetryError::Schema("vehicle: missing field".to_string()),
+        TelemetryError::VehicleNotFound("Bus".to_string()),
+        TelemetryError::Other("end of recording".to_string()),
+    ];
+    for error in errors {
+        assert_eq!(TelemetryError::from_record(&error.to_record()), error);
+    }
+    assert_eq!(
+        TelemetryError::from_record("timeout"),
+        TelemetryError::Other("timeout".to_string())
+    );
+
+    let base = Duration::from_millis(1500);
+    let max = Duration::from_secs(10);
+    let mut refused = TelemetryError::ConnectionRefused(String::new()).backoff(base, max);
+    let waits: Vec<Duration> = (0..6).map(|_| refused.next_wait()).collect();
+    assert_eq!(waits[0], base);
+    assert_eq!(waits[5], Duration::from_secs(30));
+
+    // another vehicle is looked for at once
+    let mut not_found = TelemetryError::VehicleNotFound(String::new()).backoff(base, max);
+    assert_eq!(not_found.next_wait(), Duration::ZERO);
+}
+
+#[tokio::test]
+async fn test_http_source_errors() {
+    let mut source = source(free_port(), Duration::from_secs(5));
+    let e = source.current_vehicle_name().await.unwrap_err();
+    assert!(e.is_not_running(), "{:?}", e);
+
+    let api = MockApi::new();
+    let server = MockServer::start(api, SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .await
+        .unwrap();
+    let mut source = self::source(server.addr().port(), Duration::from_secs(5));
+    assert_eq!(
+        source.vehicle("Gone").await.unwrap_err(),
+        TelemetryError::VehicleNotFound("Gone".to_string())
+    );
+
+    // accepts, but never answers
+    let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut source = self::source(silent.local_addr().unwrap().port(), Duration::from_millis(200));
+    let e = source.world().await.unwrap_err();
+    assert!(matches!(e, TelemetryError::Timeout(_)), "{:?}", e);
+}
+
+#[tokio::test]
+async fn test_bridge_signals_thebus_not_running() {
+    let port = free_port();
+    let sink = MemorySink::new();
+    let mut bridge = Bridge::new(source(port, Duration::from_secs(5)))
+        .options(
+            BridgeOptions::default()
+                .interval(Duration::ZERO)
+                .error_interval(Duration::ZERO),
+        )
+        .sink(Box::new(sink.clone()));
+
+    bridge.step().await;
+    bridge.step().await;
+    assert!(!bridge.thebus_running());
+    // only once
+    assert_eq!(String::from_utf8(sink.output()).unwrap(), "O0\n");
+
+    // TheBus is started
+    let api = MockApi::new();
+    let _server = MockServer::start(api, SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+        .await
+        .unwrap();
+    bridge.step().await;
+    assert!(bridge.thebus_running());
+    assert_eq!(String::from_utf8(sink.output()).unwrap(), "O0\nO1\n");
+}
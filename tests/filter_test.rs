@@ -0,0 +1,52 @@
+use the_bus_2_komsi::filter::{CommandFilter, FilteredSink};
+use the_bus_2_komsi::sink::{KomsiSink, MemorySink};
+
+#[test]
+fn test_filter_parse() {
+    let filter = CommandFilter::parse("s, x y").unwrap();
+    assert!(filter.allows('s'));
+    assert!(filter.allows('y'));
+    assert!(!filter.allows('A'));
+    // SimulatorType always passes
+    assert!(filter.allows('O'));
+
+    let filter = CommandFilter::parse("A-M").unwrap();
+    assert!(filter.allows('A'));
+    assert!(filter.allows('M'));
+    assert!(!filter.allows('N'));
+
+    let filter = CommandFilter::parse("ABr").unwrap();
+    assert!(filter.allows('B'));
+    assert!(filter.allows('r'));
+
+    assert!(CommandFilter::all().allows('A'));
+    assert!(CommandFilter::parse("").is_err());
+    assert!(CommandFilter::parse("M-A").is_err());
+    assert!(CommandFilter::parse("Q").is_err());
+}
+
+#[test]
+fn test_filter_apply() {
+    let filter = CommandFilter::parse("s, x, y").unwrap();
+    assert_eq!(filter.apply(b"A1B1x99y50r20260101094348\n"), b"x99y50\n");
+    assert_eq!(filter.apply(b"A1B1\n"), b"");
+    assert_eq!(filter.apply(b"O1\n"), b"O1\n");
+
+    let frame = b"A1B1x99\n";
+    assert_eq!(CommandFilter::all().apply(frame), frame);
+}
+
+#[test]
+fn test_filtered_sink() {
+    let lamps = MemorySink::new();
+    let mut sink = FilteredSink::new(
+        Box::new(lamps.clone()),
+        CommandFilter::parse("A-N").unwrap(),
+    );
+
+    sink.send(b"O1\n").unwrap();
+    sink.send(b"y50\n").unwrap();
+    sink.send(b"G1y60\n").unwrap();
+
+    assert_eq!(lamps.output(), b"O1\nG1\n");
+}
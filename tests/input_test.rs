@@ -0,0 +1,63 @@
+use configparser::ini::Ini;
+use komsi::KomsiCommand;
+use the_bus_2_komsi::input::{InputMap, KomsiDecoder, MAX_LINE};
+
+#[test]
+fn test_decoder_splits_lines_into_commands() {
+    let mut decoder = KomsiDecoder::new();
+
+    // incomplete line, nothing decoded yet
+    assert!(decoder.push(b"A1H").is_empty());
+
+    let commands = decoder.push(b"1\r\ny57\n");
+    assert_eq!(
+        commands,
+        vec![
+            KomsiCommand::Ignition(true),
+            KomsiCommand::FrontDoor(true),
+            KomsiCommand::Speed(57),
+        ]
+    );
+}
+
+#[test]
+fn test_decoder_skips_garbage() {
+    let mut decoder = KomsiDecoder::new();
+
+    let commands = decoder.push(b"#?Q1D2\n");
+    assert_eq!(commands, vec![KomsiCommand::Indicator(2)]);
+}
+
+#[test]
+fn test_decoder_drops_overlong_line() {
+    let mut decoder = KomsiDecoder::new();
+
+    // a device without end of line, e.g. at the wrong baudrate
+    let noise = vec![b'H'; MAX_LINE * 4];
+    assert!(decoder.push(&noise).is_empty());
+    assert!(decoder.push(b"1\n").is_empty());
+
+    // the next line is decoded again
+    assert_eq!(decoder.push(b"H1\n"), vec![KomsiCommand::FrontDoor(true)]);
+}
+
+#[test]
+fn test_input_map_from_ini() {
+    let mut ini = Ini::new_cs();
+    ini.read(
+        "[input]\nH1 = DoorFrontOpen\nD1 = IndicatorLeft\nd1 = NotAnIndicator\nX1 = Unknown\n"
+            .to_string(),
+    )
+    .unwrap();
+
+    let mut problems = Vec::new();
+    let map = InputMap::from_ini(&ini, false, &mut problems);
+
+    assert_eq!(map.action(&KomsiCommand::FrontDoor(true)), Some("DoorFrontOpen"));
+    assert_eq!(map.action(&KomsiCommand::Indicator(1)), Some("IndicatorLeft"));
+    assert_eq!(map.action(&KomsiCommand::DebugMode(1)), Some("NotAnIndicator"));
+    assert_eq!(map.action(&KomsiCommand::FrontDoor(false)), None);
+    // X is no KOMSI command
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].message.starts_with("Input mapping X1"));
+}
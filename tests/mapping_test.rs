@@ -0,0 +1,95 @@
+use std::fs;
+
+use configparser::ini::Ini;
+use komsi::vehicle::VehicleState;
+use serde_json::Value;
+use the_bus_2_komsi::mapping::{
+    append_commands, compare_extra, Conversion, ExtraValues, Mapping, MappingEntry,
+};
+
+fn load_json(file_path: &str) -> Value {
+    let json = fs::read_to_string(file_path)
+        .unwrap_or_else(|_| panic!("Failed to read {}", file_path));
+    serde_json::from_str(&json).unwrap_or_else(|_| panic!("Failed to parse {}", file_path))
+}
+
+#[test]
+fn test_conversions() {
+    assert_eq!(Conversion::Bool.apply(0.3), 1);
+    assert_eq!(Conversion::Bool.apply(0.0), 0);
+    assert_eq!(Conversion::Round.apply(540.85), 541);
+    assert_eq!(Conversion::Round.apply(-3.0), 0);
+    assert_eq!(Conversion::Abs.apply(-3.4), 3);
+    assert_eq!(Conversion::Scale(100.0).apply(0.96659), 97);
+    assert_eq!(Conversion::Threshold(0.5).apply(0.7), 1);
+    assert_eq!(Conversion::Threshold(0.5).apply(0.5), 0);
+
+    assert_eq!(Conversion::parse("scale 10"), Ok(Conversion::Scale(10.0)));
+    assert!(Conversion::parse("scale").is_err());
+    assert!(Conversion::parse("square").is_err());
+}
+
+#[test]
+fn test_mapping_entry_parse() {
+    let entry = MappingEntry::parse("t", "RPM, round").unwrap();
+    assert_eq!(entry.code, 't');
+    assert_eq!(entry.path, "RPM");
+    assert_eq!(entry.conversion, Conversion::Round);
+
+    let entry = MappingEntry::parse("H", "AllLamps.ButtonLight Door 1").unwrap();
+    assert_eq!(entry.path, "AllLamps.ButtonLight Door 1");
+    assert_eq!(entry.conversion, Conversion::Round);
+
+    assert!(MappingEntry::parse("Q", "RPM").is_err());
+    assert!(MappingEntry::parse("tt", "RPM").is_err());
+    assert!(MappingEntry::parse("r", "DateTime").is_err());
+    assert!(MappingEntry::parse("t", "RPM, scale").is_err());
+}
+
+#[test]
+fn test_mapping_apply() {
+    let mut ini = Ini::new_cs();
+    ini.read(
+        "[mapping]\nt = RPM, round\nv = EngineTemperature, scale 1000\nE = Brake, threshold 0.5\ny = Location.X, round\nz = DoesNotExist\n"
+            .to_string(),
+    )
+    .unwrap();
+    let mapping = Mapping::from_ini(&ini, false, &mut Vec::new());
+
+    let vehicle = load_json("tests/json/scania_citywide.json");
+    let mut state = VehicleState::new();
+    let extra = mapping.apply(&vehicle, &mut state);
+
+    // codes of VehicleState are set directly
+    assert!(state.fixing_brake);
+    assert!(state.speed > 0);
+
+    // all other codes are extra values, missing values are skipped
+    assert_eq!(extra.get(&'t'), Some(&531));
+    assert_eq!(extra.get(&'v'), Some(&17));
+    assert_eq!(extra.get(&'z'), None);
+    assert_eq!(extra.len(), 2);
+}
+
+#[test]
+fn test_compare_extra() {
+    let mut old = ExtraValues::new();
+    old.insert('t', 500);
+    old.insert('v', 17);
+
+    let mut new = old.clone();
+    new.insert('t', 540);
+
+    assert_eq!(compare_extra(&old, &new, false, None), b"t540");
+    assert_eq!(compare_extra(&old, &new, true, None), b"t540v17");
+    assert!(compare_extra(&new, &new, false, None).is_empty());
+
+    // one end of line at the end
+    let mut cmdbuf = b"A1\n".to_vec();
+    append_commands(&mut cmdbuf, b"t540");
+    assert_eq!(cmdbuf, b"A1t540\n");
+
+    let mut cmdbuf = Vec::new();
+    append_commands(&mut cmdbuf, b"t540");
+    assert_eq!(cmdbuf, b"t540\n");
+}
@@ -0,0 +1,114 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+use the_bus_2_komsi::mockapi::{read_fixture, scenario, MockApi, MockServer};
+use the_bus_2_komsi::source::{HttpSource, TelemetrySource};
+use the_bus_telemetry::api::RequestConfig;
+
+const CITEA: &str = "BP_VDL_Citea_LLE_120_2D_C_2147124848";
+
+async fn start(api: &MockApi) -> (MockServer, HttpSource) {
+    let server = MockServer::start(api.clone(), SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .await
+        .unwrap();
+    let config = RequestConfig::new()
+        .port(server.addr().port().to_string())
+        .timeout(Duration::from_secs(5));
+    (server, HttpSource::new(config))
+}
+
+#[test]
+fn test_mock_api_routes() {
+    let api = MockApi::from_dir(Path::new("tests/json")).unwrap();
+    assert_eq!(api.vehicle_names().len(), 5);
+
+    let (status, player) = api.handle("/player");
+    assert_eq!(status, 200);
+    assert_eq!(player["Mode"], "Walking");
+
+    api.enter(CITEA);
+    let (_, player) = api.handle("/Player");
+    assert_eq!(player["CurrentVehicle"], CITEA);
+
+    let (status, vehicle) = api.handle("/Vehicles/Current");
+    assert_eq!(status, 200);
+    assert_eq!(vehicle["VehicleModel"], "Citea LLE");
+
+    let (status, _) = api.handle(&format!("/vehicles/{}", CITEA.to_lowercase()));
+    assert_eq!(status, 200);
+
+    let (status, _) = api.handle("/vehicles/BP_Unknown");
+    assert_eq!(status, 404);
+
+    let (_, world) = api.handle("/World");
+    assert_eq!(world["DateTime"], "2026-01-01T09:43:48");
+    api.advance_clock(Duration::from_secs(60));
+    let (_, world) = api.handle("/world");
+    assert_eq!(world["DateTime"], "2026-01-01T09:44:48");
+
+    api.handle(&format!("/vehicles/{}/sendeventpress?event=DoorFrontOpen", CITEA));
+    assert_eq!(api.commands(), vec!["sendeventpress?event=DoorFrontOpen"]);
+}
+
+#[test]
+fn test_mock_api_scenario() {
+    let fixture = read_fixture(Path::new("tests/json/vdl_citea.json")).unwrap();
+    let frames = scenario(&fixture);
+
+    let api = MockApi::new();
+    api.add_frames(frames.clone());
+    api.requests_per_frame(2);
+    api.enter(CITEA);
+
+    let served: Vec<_> = (0..frames.len() * 2 + 2)
+        .map(|_| api.handle("/vehicles/current").1)
+        .collect();
+
+    assert_eq!(served[0]["IgnitionEnabled"], "false");
+    assert_eq!(served[1]["IgnitionEnabled"], "false");
+    assert_eq!(served[2]["IgnitionEnabled"], "true");
+    assert!(served.iter().any(|f| f["PassengerDoorsOpen"] == "true"));
+    assert!(served.iter().any(|f| f["IndicatorState"] == -1));
+    assert!(served.iter().any(|f| f["Speed"] == 50.0));
+
+    // the last frame stays
+    assert_eq!(served[served.len() - 1], frames[frames.len() - 1]);
+}
+
+#[tokio::test]
+async fn test_mock_api_http() {
+    let api = MockApi::from_dir(Path::new("tests/json")).unwrap();
+    let (_server, mut source) = start(&api).await;
+
+    assert_eq!(source.current_vehicle_name().await.unwrap(), "");
+
+    api.enter(CITEA);
+    let name = source.current_vehicle_name().await.unwrap();
+    assert_eq!(name, CITEA);
+
+    let vehicle = source.vehicle(&name).await.unwrap();
+    assert_eq!(vehicle["ActorName"], CITEA);
+
+    let world = source.world().await.unwrap();
+    assert_eq!(world["LevelName"], "Berlin");
+
+    source.send_action(&name, "DoorFrontOpen").await.unwrap();
+    assert_eq!(
+        api.commands(),
+        vec![
+            "sendeventpress?event=DoorFrontOpen",
+            "sendeventrelease?event=DoorFrontOpen"
+        ]
+    );
+
+    // the event name is one query value, whatever it contains
+    source.send_action(&name, "Horn & Light=1").await.unwrap();
+    assert_eq!(
+        api.commands()[2..],
+        [
+            "sendeventpress?event=Horn+%26+Light%3D1",
+            "sendeventrelease?event=Horn+%26+Light%3D1"
+        ]
+    );
+}
@@ -0,0 +1,231 @@
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use configparser::ini::Ini;
+use rumqttc::{ConnAck, ConnectReturnCode, Packet, Publish, QoS, SubAck, SubscribeReasonCode};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use the_bus_2_komsi::mqtt::{MqttConfig, MqttSink};
+use the_bus_2_komsi::sink::KomsiSink;
+
+const MAX_PACKET: usize = 64 * 1024;
+
+/// Stands in for a broker with a single client, the test drives it packet by packet
+struct FakeBroker {
+    stream: TcpStream,
+    buffer: BytesMut,
+}
+
+impl FakeBroker {
+    async fn accept(listener: &TcpListener) -> Self {
+        let (stream, _) = timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        Self {
+            stream,
+            buffer: BytesMut::new(),
+        }
+    }
+
+    async fn next_packet(&mut self) -> Packet {
+        loop {
+            if let Ok(packet) = Packet::read(&mut self.buffer, MAX_PACKET) {
+                return packet;
+            }
+            let n = timeout(Duration::from_secs(5), self.stream.read_buf(&mut self.buffer))
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(n > 0, "client closed the connection");
+        }
+    }
+
+    /// The next publish, pings and acks are skipped
+    async fn next_publish(&mut self) -> Publish {
+        loop {
+            if let Packet::Publish(publish) = self.next_packet().await {
+                return publish;
+            }
+        }
+    }
+
+    async fn write(&mut self, packet: Packet) {
+        let mut buffer = BytesMut::new();
+        packet.write(&mut buffer, MAX_PACKET).unwrap();
+        self.stream.write_all(&buffer).await.unwrap();
+    }
+}
+
+fn topic_payload(publish: &Publish) -> (String, String, bool) {
+    (
+        publish.topic.clone(),
+        String::from_utf8(publish.payload.to_vec()).unwrap(),
+        publish.retain,
+    )
+}
+
+/// Polls until the condition is true, the sink works in a task
+async fn wait_for(mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(Instant::now() < deadline, "timeout");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[test]
+fn test_mqtt_config_from_ini() {
+    let mut ini = Ini::new();
+    ini.read(
+        "[mqtt]\nbroker = 192.168.1.10\ntopic = cockpit/\nusername = bus\npassword = secret\nfilter = A, y\n"
+            .to_string(),
+    )
+    .unwrap();
+
+    let config = MqttConfig::from_ini(&ini, &mut Vec::new()).unwrap();
+    assert_eq!(config.host_port().unwrap(), ("192.168.1.10".to_string(), 1883));
+    assert_eq!(config.topic, "cockpit");
+    assert_eq!(config.username.as_deref(), Some("bus"));
+    assert_eq!(config.password.as_deref(), Some("secret"));
+    assert!(config.filter.allows('y'));
+    assert!(!config.filter.allows('x'));
+
+    // without broker there is no MQTT
+    assert!(MqttConfig::from_ini(&Ini::new(), &mut Vec::new()).is_none());
+}
+
+#[tokio::test]
+async fn test_mqtt_sink() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let config = MqttConfig::new(&listener.local_addr().unwrap().to_string());
+    let mut sink = MqttSink::connect(&config, false).unwrap();
+
+    // nothing is queued before the broker accepted the connection
+    assert!(!sink.connected());
+    assert_eq!(
+        sink.send(b"A1\n").unwrap_err().kind(),
+        std::io::ErrorKind::NotConnected
+    );
+
+    let mut broker = FakeBroker::accept(&listener).await;
+    let Packet::Connect(connect) = broker.next_packet().await else {
+        panic!("no connect");
+    };
+    let will = connect.last_will.unwrap();
+    assert_eq!(will.topic, "thebus/status");
+    assert_eq!(&will.message[..], b"offline");
+    assert!(will.retain);
+
+    broker
+        .write(Packet::ConnAck(ConnAck::new(ConnectReturnCode::Success, false)))
+        .await;
+    let status = broker.next_publish().await;
+    assert_eq!(
+        topic_payload(&status),
+        ("thebus/status".to_string(), "online".to_string(), true)
+    );
+    let Packet::Subscribe(subscribe) = broker.next_packet().await else {
+        panic!("no subscribe");
+    };
+    assert_eq!(subscribe.filters[0].path, "thebus/+/action");
+    broker
+        .write(Packet::SubAck(SubAck::new(
+            subscribe.pkid,
+            vec![SubscribeReasonCode::Success(QoS::AtMostOnce)],
+        )))
+        .await;
+
+    // the vehicle topic is brought up to date
+    assert_eq!(
+        topic_payload(&broker.next_publish().await),
+        ("thebus/vehicle".to_string(), String::new(), true)
+    );
+
+    // the bridge sends the whole state after connecting
+    wait_for(|| sink.reconnected()).await;
+    assert!(sink.connected());
+
+    sink.vehicle_changed("Citywide LE 1");
+    assert_eq!(
+        topic_payload(&broker.next_publish().await),
+        ("thebus/vehicle".to_string(), "Citywide LE 1".to_string(), true)
+    );
+
+    sink.send(b"A1y50r20260101094348\n").unwrap();
+    let expected = [
+        ("thebus/Citywide LE 1/ignition", "true"),
+        ("thebus/Citywide LE 1/datetime", "2026-01-01T09:43:48"),
+        ("thebus/Citywide LE 1/speed", "50"),
+    ];
+    let mut published = Vec::new();
+    for _ in expected {
+        published.push(topic_payload(&broker.next_publish().await));
+    }
+    for (topic, payload) in expected {
+        assert!(
+            published.contains(&(topic.to_string(), payload.to_string(), true)),
+            "{} missing in {:?}",
+            topic,
+            published
+        );
+    }
+
+    // only changes are published
+    sink.send(b"A1y60\n").unwrap();
+    assert_eq!(
+        topic_payload(&broker.next_publish().await),
+        ("thebus/Citywide LE 1/speed".to_string(), "60".to_string(), true)
+    );
+
+    // actions for another vehicle are ignored
+    broker
+        .write(Packet::Publish(Publish::new(
+            "thebus/Other/action",
+            QoS::AtMostOnce,
+            "DoorFrontOpen",
+        )))
+        .await;
+    broker
+        .write(Packet::Publish(Publish::new(
+            "thebus/Citywide LE 1/action",
+            QoS::AtMostOnce,
+            "BusStopBrake",
+        )))
+        .await;
+
+    let mut actions = Vec::new();
+    wait_for(|| {
+        actions.extend(sink.receive_actions());
+        !actions.is_empty()
+    })
+    .await;
+    assert_eq!(actions, vec!["BusStopBrake".to_string()]);
+
+    // the broker is gone, the bridge skips the sink until it is back
+    drop(broker);
+    wait_for(|| !sink.connected()).await;
+    assert_eq!(
+        sink.send(b"y70\n").unwrap_err().kind(),
+        std::io::ErrorKind::NotConnected
+    );
+
+    let mut broker = FakeBroker::accept(&listener).await;
+    let Packet::Connect(_) = broker.next_packet().await else {
+        panic!("no connect");
+    };
+    broker
+        .write(Packet::ConnAck(ConnAck::new(ConnectReturnCode::Success, false)))
+        .await;
+    wait_for(|| sink.reconnected()).await;
+    assert!(sink.connected());
+
+    // the vehicle is published again and the whole state has the speed of the meantime
+    let mut published = Vec::new();
+    sink.send_full_state(b"\n").unwrap();
+    while !published.contains(&("thebus/Citywide LE 1/speed".to_string(), "70".to_string(), true)) {
+        published.push(topic_payload(&broker.next_publish().await));
+    }
+    assert!(published.contains(&("thebus/vehicle".to_string(), "Citywide LE 1".to_string(), true)));
+}
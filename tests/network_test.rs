@@ -0,0 +1,220 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use komsi::KomsiCommand;
+use the_bus_2_komsi::config::Config;
+use the_bus_2_komsi::network::{
+    NetworkOptions, NetworkTarget, TcpClientSink, TcpServerSink, UdpSink,
+};
+use the_bus_2_komsi::sink::KomsiSink;
+use tokio_util::sync::CancellationToken;
+
+fn options() -> NetworkOptions {
+    NetworkOptions {
+        init: b"O1\n".to_vec(),
+        verbose: false,
+        ..Default::default()
+    }
+}
+
+/// Polls until the condition is true, the sinks work in tasks
+fn wait_for(mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(Instant::now() < deadline, "timeout");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn read_exact(stream: &mut TcpStream, len: usize) -> String {
+    let mut buffer = vec![0u8; len];
+    stream.read_exact(&mut buffer).unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+// the dashboards below block, the tasks of the sinks run on the worker threads
+#[tokio::test(flavor = "multi_thread")]
+async fn test_tcp_server_sink() {
+    let mut sink = TcpServerSink::bind("127.0.0.1:0", options()).unwrap();
+    assert_eq!(
+        sink.send(b"A1\n").unwrap_err().kind(),
+        std::io::ErrorKind::NotConnected
+    );
+
+    let mut first = TcpStream::connect(sink.address()).unwrap();
+    first.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    wait_for(|| sink.clients() == 1);
+    assert_eq!(read_exact(&mut first, 3), "O1\n");
+    assert!(sink.reconnected());
+    sink.send_full_state(b"A1B1\n").unwrap();
+    assert_eq!(read_exact(&mut first, 5), "A1B1\n");
+
+    // the second dashboard gets the whole state, the first one is not bothered
+    let mut second = TcpStream::connect(sink.address()).unwrap();
+    second.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    wait_for(|| sink.clients() == 2);
+    assert!(sink.reconnected());
+    sink.send_full_state(b"A1B1\n").unwrap();
+    sink.send(b"y50\n").unwrap();
+    assert_eq!(read_exact(&mut second, 12), "O1\nA1B1\ny50\n");
+    assert_eq!(read_exact(&mut first, 4), "y50\n");
+
+    // commands from the dashboards
+    first.write_all(b"H1\n").unwrap();
+    let mut received = Vec::new();
+    wait_for(|| {
+        received.extend(sink.receive());
+        !received.is_empty()
+    });
+    assert_eq!(received, vec![KomsiCommand::FrontDoor(true)]);
+
+    drop(first);
+    wait_for(|| sink.clients() == 1);
+}
+
+/// A dashboard which does not read must not hold up the bridge or the other dashboards
+#[tokio::test(flavor = "multi_thread")]
+async fn test_tcp_server_sink_stuck_dashboard() {
+    let mut sink = TcpServerSink::bind("127.0.0.1:0", options()).unwrap();
+
+    let _stuck = TcpStream::connect(sink.address()).unwrap();
+    let mut reading = TcpStream::connect(sink.address()).unwrap();
+    reading.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    wait_for(|| sink.clients() == 2);
+
+    // more than the socket buffers take
+    let mut frame = vec![b'y'; 16 * 1024 * 1024];
+    frame.push(b'\n');
+    let len = 3 + frame.len();
+    let reader = thread::spawn(move || {
+        let read = read_exact(&mut reading, len).len();
+        (reading, read)
+    });
+
+    let start = Instant::now();
+    sink.send(&frame).unwrap();
+    assert!(start.elapsed() < Duration::from_millis(100));
+
+    let (_reading, read) = reader.join().unwrap();
+    assert_eq!(read, len);
+    wait_for(|| sink.clients() == 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_tcp_client_sink() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+
+    let mut sink = TcpClientSink::connect(&address, options());
+    let (mut dashboard, _) = listener.accept().unwrap();
+    dashboard
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    assert_eq!(read_exact(&mut dashboard, 3), "O1\n");
+
+    wait_for(|| sink.reconnected());
+    sink.send(b"y50\n").unwrap();
+    assert_eq!(read_exact(&mut dashboard, 4), "y50\n");
+
+    // the dashboard restarts, the sink connects again
+    drop(dashboard);
+    let (mut dashboard, _) = listener.accept().unwrap();
+    dashboard
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    assert_eq!(read_exact(&mut dashboard, 3), "O1\n");
+    wait_for(|| sink.reconnected());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_udp_sink() {
+    let dashboard = UdpSocket::bind("127.0.0.1:0").unwrap();
+    dashboard
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let address = dashboard.local_addr().unwrap().to_string();
+
+    let mut sink = UdpSink::bind(&address, options());
+    let mut buffer = [0u8; 64];
+
+    let (n, from) = dashboard.recv_from(&mut buffer).unwrap();
+    assert_eq!(&buffer[..n], b"O1\n");
+
+    sink.send(b"y50\n").unwrap();
+    let (n, _) = dashboard.recv_from(&mut buffer).unwrap();
+    assert_eq!(&buffer[..n], b"y50\n");
+
+    // the init frame is part of the whole state, the dashboard may have restarted
+    sink.send_full_state(b"A1\n").unwrap();
+    let (n, _) = dashboard.recv_from(&mut buffer).unwrap();
+    assert_eq!(&buffer[..n], b"O1\nA1\n");
+
+    dashboard.send_to(b"L1\n", from).unwrap();
+    let mut received = Vec::new();
+    wait_for(|| {
+        received.extend(sink.receive());
+        !received.is_empty()
+    });
+    assert_eq!(received, vec![KomsiCommand::StopBrake(true)]);
+}
+
+#[test]
+fn test_network_config() {
+    let path = std::env::temp_dir().join(format!("thebus2komsi_network_{}.ini", std::process::id()));
+    fs::write(
+        &path,
+        "[network]\ntcp = 192.168.1.50:5000\ntcpfilter = s, y\nudp2 = 192.168.1.255:5000\n",
+    )
+    .unwrap();
+
+    let config = Config::load(&path, false);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(config.network.len(), 2);
+    assert_eq!(
+        config.network[0].target,
+        NetworkTarget::TcpClient("192.168.1.50:5000".to_string())
+    );
+    assert!(config.network[0].filter.allows('y'));
+    assert!(!config.network[0].filter.allows('A'));
+    assert_eq!(
+        config.network[1].target,
+        NetworkTarget::Udp("192.168.1.255:5000".to_string())
+    );
+    assert!(config.network[1].filter.is_all());
+
+    // no default serial port if all dashboards are on the network
+    assert!(config.ports.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_tcp_server_sink_shutdown() {
+    let shutdown = CancellationToken::new();
+    let mut sink = TcpServerSink::bind(
+        "127.0.0.1:0",
+        NetworkOptions {
+            shutdown: shutdown.clone(),
+            ..options()
+        },
+    )
+    .unwrap();
+
+    let mut dashboard = TcpStream::connect(sink.address()).unwrap();
+    dashboard.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    wait_for(|| sink.clients() == 1);
+
+    // no new dashboards, but the last frame still goes out
+    shutdown.cancel();
+    thread::sleep(Duration::from_millis(300));
+    assert!(TcpStream::connect(sink.address()).is_err());
+    sink.send(b"A0\n").unwrap();
+
+    sink.close().unwrap();
+    let mut rest = String::new();
+    dashboard.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "O1\nA0\n");
+    assert_eq!(sink.clients(), 0);
+}
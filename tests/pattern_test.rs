@@ -0,0 +1,43 @@
+use komsi::KomsiDateTime;
+use the_bus_2_komsi::bridge::{safe_state_frame, DEFAULT_SAFE_STATE};
+use the_bus_2_komsi::pattern::test_pattern;
+
+#[test]
+fn test_test_pattern() {
+    let date_time = KomsiDateTime {
+        year: 2026,
+        month: 1,
+        day: 2,
+        hour: 9,
+        min: 43,
+        sec: 48,
+    };
+    let steps = test_pattern(date_time);
+    let frames: Vec<String> = steps
+        .iter()
+        .map(|step| String::from_utf8(step.frame.clone()).unwrap())
+        .collect();
+
+    // starts and ends with everything off
+    let off = String::from_utf8(safe_state_frame(DEFAULT_SAFE_STATE).unwrap()).unwrap();
+    assert_eq!(frames[0], off);
+    assert_eq!(frames[frames.len() - 1], off);
+
+    // one lamp at a time
+    assert_eq!(frames[1], "A1\n");
+    assert_eq!(frames[2], "A0B1\n");
+    // D is the indicator, it is no lamp
+    assert_eq!(frames[4], "C0E1\n");
+    assert!(steps.iter().all(|s| s.name != "lamp D"));
+    assert_eq!(frames[12], "L0M1\n");
+    assert_eq!(frames[13], "M0\n");
+
+    assert!(frames.contains(&"y0s0\n".to_string()));
+    assert!(frames.contains(&"y100s100\n".to_string()));
+    assert!(frames.contains(&"x100\n".to_string()));
+    for indicator in ["D0\n", "D1\n", "D2\n", "D3\n"] {
+        assert!(frames.contains(&indicator.to_string()));
+    }
+    assert!(frames.contains(&"r20260102094348\n".to_string()));
+    assert_eq!(steps.iter().find(|s| s.frame.starts_with(b"r")).unwrap().name, "clock 2026-01-02 09:43");
+}
@@ -0,0 +1,154 @@
+// The whole pipeline: mock API -> HTTP -> bridge -> KOMSI byte stream
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+use the_bus_2_komsi::bridge::{init_frame, Bridge, BridgeOptions};
+use the_bus_2_komsi::mockapi::{read_fixture, scenario, MockApi, MockServer};
+use the_bus_2_komsi::sink::MemorySink;
+use the_bus_2_komsi::source::HttpSource;
+use the_bus_telemetry::api::RequestConfig;
+
+const CITEA: &str = "BP_VDL_Citea_LLE_120_2D_C_2147124848";
+
+struct Pipeline {
+    api: MockApi,
+    _server: MockServer,
+    bridge: Bridge<HttpSource>,
+    sink: MemorySink,
+    sent: usize,
+}
+
+impl Pipeline {
+    async fn start(api: MockApi, options: BridgeOptions) -> Self {
+        let server = MockServer::start(api.clone(), SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+            .await
+            .unwrap();
+        let config = RequestConfig::new()
+            .port(server.addr().port().to_string())
+            .timeout(Duration::from_secs(5));
+
+        let sink = MemorySink::new();
+        let bridge = Bridge::new(HttpSource::new(config))
+            .options(options.interval(Duration::ZERO).error_interval(Duration::ZERO))
+            .sink(Box::new(sink.clone()));
+
+        Self {
+            api,
+            _server: server,
+            bridge,
+            sink,
+            sent: 0,
+        }
+    }
+
+    /// One poll, returns what was written to the sink
+    async fn step(&mut self) -> String {
+        self.bridge.step().await;
+        let output = self.sink.output();
+        let new = String::from_utf8(output[self.sent..].to_vec()).unwrap();
+        self.sent = output.len();
+        new
+    }
+}
+
+fn citea() -> serde_json::Value {
+    read_fixture(Path::new("tests/json/vdl_citea.json")).unwrap()
+}
+
+#[tokio::test]
+async fn test_pipeline_scenario() {
+    let api = MockApi::new();
+    api.add_frames(scenario(&citea()));
+    api.enter(CITEA);
+
+    let mut pipeline = Pipeline::start(api, BridgeOptions::default()).await;
+
+    pipeline.bridge.send_to_sinks(&init_frame());
+    let mut stream = String::new();
+    for _ in 0..14 {
+        stream += &pipeline.step().await;
+    }
+
+    assert_eq!(
+        stream,
+        concat!(
+            "O1\n",                     // SimulatorType TheBus
+            "E1H1x99r20260101094348\n", // parked
+            "A1\n",                     // ignition
+            "B1\n",                     // engine
+            "C1\n",                     // doors open
+            "C0\n",                     // doors closed
+            "E0D1\n",                   // fixing brake off, indicator left
+            "y10\n",
+            "D0y20\n",
+            "y30\n",
+            "y40\n",
+            "y50\n",
+            "y30\n",
+            "y10\n",
+            "E1y0\n",
+        )
+    );
+
+    // the scenario is over, nothing changes any more
+    assert_eq!(pipeline.step().await, "");
+}
+
+#[tokio::test]
+async fn test_pipeline_refresh_after_reentering() {
+    let api = MockApi::new();
+    api.add_vehicle(citea());
+    api.enter(CITEA);
+
+    // read the vehicle name on every poll to notice leaving the bus at once
+    let options = BridgeOptions {
+        vehicle_name_interval: Duration::ZERO,
+        ..Default::default()
+    };
+    let mut pipeline = Pipeline::start(api, options).await;
+
+    let first = pipeline.step().await;
+    assert_eq!(first, "A1B1C1E1H1x99r20260101094348\n");
+    assert_eq!(pipeline.step().await, "");
+
+    pipeline.api.leave();
+    // the dashboard goes dark
+    assert_eq!(
+        pipeline.step().await,
+        "A0B0C0D0E0F0G0H0I0J0K0L0M0N0P0s0t0u0v0w0x0y0z0\n"
+    );
+    assert_eq!(pipeline.bridge.vehicle_name(), "");
+
+    // all values are sent again, although nothing changed
+    pipeline.api.enter(CITEA);
+    assert_eq!(pipeline.step().await, "A1B1C1E1D0F0G0K0L0H1I0J0M0x99y0s0N0P0o0r20260101094348\n");
+}
+
+#[tokio::test]
+async fn test_pipeline_datetime_update() {
+    let api = MockApi::new();
+    api.add_vehicle(citea());
+    api.enter(CITEA);
+
+    let options = BridgeOptions {
+        world_interval: Duration::from_millis(300),
+        ..Default::default()
+    };
+    let mut pipeline = Pipeline::start(api, options).await;
+
+    assert!(pipeline.step().await.ends_with("r20260101094348\n"));
+
+    // the world is only read every world_interval
+    pipeline.api.advance_clock(Duration::from_secs(60));
+    assert_eq!(pipeline.step().await, "");
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(pipeline.step().await, "r20260101094448\n");
+
+    // the seconds do not matter for KOMSI
+    pipeline.api.advance_clock(Duration::from_secs(5));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(pipeline.step().await, "");
+}
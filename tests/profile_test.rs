@@ -0,0 +1,78 @@
+use std::fs;
+
+use configparser::ini::Ini;
+use serde_json::Value;
+use the_bus_2_komsi::profile::Profiles;
+use the_bus_telemetry::api::ApiVehicleType;
+use the_bus_telemetry::api2vehicle::get_vehicle_state_from_api;
+
+const CONFIG: &str = "
+[mapping]
+t = RPM, round
+v = EngineTemperature, round
+
+[profile.lionscity]
+match = Lions City
+AllLamps.ButtonLight BusStopBrake = AllLamps.LED FixingBrake
+v = EngineTemperature, scale 1000
+
+[profile.citea]
+match = BP_VDL_Citea, BP_VDL_Other
+";
+
+fn profiles() -> Profiles {
+    let mut ini = Ini::new_cs();
+    ini.read(CONFIG.to_string()).unwrap();
+    Profiles::from_ini(&ini, false, &mut Vec::new())
+}
+
+fn load_json(file_path: &str) -> Value {
+    let json = fs::read_to_string(file_path)
+        .unwrap_or_else(|_| panic!("Failed to read {}", file_path));
+    serde_json::from_str(&json).unwrap_or_else(|_| panic!("Failed to parse {}", file_path))
+}
+
+#[test]
+fn test_profile_selection() {
+    let profiles = profiles();
+
+    let p = profiles.select("Lions City", "BP_MAN_LionsCityDD_Base_C_2147417439");
+    assert_eq!(p.map(|p| p.name.as_str()), Some("lionscity"));
+
+    let p = profiles.select("", "BP_VDL_Citea_LLE_120_2D_C_2147124848");
+    assert_eq!(p.map(|p| p.name.as_str()), Some("citea"));
+
+    assert!(profiles.select("eCitybus", "BP_Mercedes_eCitaro_12m_2Door_C_2147345014").is_none());
+}
+
+#[test]
+fn test_profile_mapping_overrides_base() {
+    let profiles = profiles();
+
+    let base = profiles.mapping(None);
+    let lionscity = profiles.mapping(profiles.select("Lions City", ""));
+
+    let v = |m: &the_bus_2_komsi::mapping::Mapping| {
+        m.entries.iter().find(|e| e.code == 'v').unwrap().conversion
+    };
+
+    assert_eq!(base.entries.len(), 2);
+    assert_eq!(lionscity.entries.len(), 2);
+    assert_ne!(v(&base), v(&lionscity));
+}
+
+#[test]
+fn test_profile_aliases() {
+    let profiles = profiles();
+    let profile = profiles.select("Lions City", "").unwrap();
+
+    let mut vehicle = load_json("tests/json/man_lionscity.json");
+    let plain: ApiVehicleType = serde_json::from_value(vehicle.clone()).unwrap();
+    assert!(!get_vehicle_state_from_api(plain).lights_stop_brake);
+
+    profile.apply_aliases(&mut vehicle);
+    assert!(vehicle["AllLamps"].get("LED FixingBrake").is_none());
+
+    let aliased: ApiVehicleType = serde_json::from_value(vehicle).unwrap();
+    assert!(get_vehicle_state_from_api(aliased).lights_stop_brake);
+}
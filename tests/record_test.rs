@@ -0,0 +1,94 @@
+use std::fs;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use the_bus_2_komsi::bridge::{Bridge, BridgeOptions};
+use the_bus_2_komsi::record::{RecordEntry, RecordingSource, ReplaySource, KIND_VEHICLE};
+use the_bus_2_komsi::sink::MemorySink;
+use the_bus_2_komsi::source::{SourceError, TelemetrySource};
+
+/// Drives faster with every request
+struct MockSource {
+    vehicle: Value,
+    speed: f64,
+}
+
+impl TelemetrySource for MockSource {
+    async fn current_vehicle_name(&mut self) -> Result<String, SourceError> {
+        Ok(self.vehicle["ActorName"].as_str().unwrap().to_string())
+    }
+
+    async fn vehicle(&mut self, _name: &str) -> Result<Value, SourceError> {
+        self.speed += 3.0;
+        self.vehicle["Speed"] = json!(self.speed);
+        Ok(self.vehicle.clone())
+    }
+
+    async fn world(&mut self) -> Result<Value, SourceError> {
+        Ok(json!({
+            "LevelName": "Berlin",
+            "DateTime": "2026-01-01T09:43:48",
+            "TimeFactor": 1.0,
+            "BaseLatitude": 52.5,
+            "BaseLongitude": 13.4
+        }))
+    }
+
+    async fn send_action(&mut self, _name: &str, _action: &str) -> Result<(), SourceError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_record_entry_json() {
+    let entry = RecordEntry {
+        t: 1200,
+        kind: KIND_VEHICLE.to_string(),
+        response: Err("timeout".to_string()),
+    };
+    assert_eq!(RecordEntry::from_json(&entry.to_json()), Ok(entry));
+
+    assert!(RecordEntry::from_json(&json!({"t": 1, "kind": "vehicle"})).is_err());
+}
+
+#[tokio::test]
+async fn test_record_and_replay() {
+    let path = std::env::temp_dir().join(format!("thebus2komsi_record_{}.jsonl", std::process::id()));
+
+    let json = fs::read_to_string("tests/json/vdl_citea.json").unwrap();
+    let source = MockSource {
+        vehicle: serde_json::from_str(&json).unwrap(),
+        speed: 0.0,
+    };
+
+    // record some steps
+    let recorded = MemorySink::new();
+    {
+        let source = RecordingSource::create(source, &path).unwrap();
+        let mut bridge = Bridge::new(source)
+            .options(BridgeOptions::default().interval(Duration::ZERO))
+            .sink(Box::new(recorded.clone()));
+        for _ in 0..5 {
+            bridge.step().await;
+        }
+    }
+
+    // the replay must produce the same KOMSI commands
+    let replayed = MemorySink::new();
+    let source = ReplaySource::open(&path, 100.0, false).unwrap();
+    let mut bridge = Bridge::new(source)
+        .options(
+            BridgeOptions::default()
+                .interval(Duration::ZERO)
+                .error_interval(Duration::ZERO),
+        )
+        .sink(Box::new(replayed.clone()));
+    bridge.run().await;
+
+    fs::remove_file(&path).unwrap();
+
+    let replayed = String::from_utf8(replayed.output()).unwrap();
+    let recorded = String::from_utf8(recorded.output()).unwrap();
+    assert!(recorded.contains("y15"));
+    assert_eq!(replayed, format!("O1\n{}", recorded));
+}
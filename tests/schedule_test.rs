@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+
+use komsi::vehicle::VehicleState;
+use the_bus_2_komsi::mapping::ExtraValues;
+use the_bus_2_komsi::schedule::{keep_slow_values, Backoff, Pacer, Timer};
+
+#[test]
+fn test_timer_and_backoff() {
+    let mut timer = Timer::new(Duration::from_secs(60));
+    assert!(timer.due());
+    timer.done();
+    assert!(!timer.due());
+    timer.reset();
+    assert!(timer.due());
+
+    let mut backoff = Backoff::new(Duration::from_millis(1500), Duration::from_secs(5));
+    let waits: Vec<u128> = (0..4).map(|_| backoff.next_wait().as_millis()).collect();
+    assert_eq!(waits, vec![1500, 3000, 5000, 5000]);
+    backoff.reset();
+    assert_eq!(backoff.next_wait(), Duration::from_millis(1500));
+}
+
+#[tokio::test]
+async fn test_pacer_reports_overruns() {
+    let interval = Duration::from_millis(50);
+    let mut pacer = Pacer::new(interval);
+
+    // in time
+    let start = Instant::now();
+    pacer.wait().await;
+    assert!(start.elapsed() >= interval);
+    assert_eq!(pacer.overrun_report(), None);
+
+    // a slow poll, the next one is not hurried
+    tokio::time::sleep(Duration::from_millis(120)).await;
+    pacer.wait().await;
+    let (overruns, worst) = pacer.overrun_report().unwrap();
+    assert_eq!(overruns, 1);
+    assert!(worst >= Duration::from_millis(60));
+
+    let start = Instant::now();
+    pacer.wait().await;
+    assert!(start.elapsed() >= Duration::from_millis(40));
+
+    // without an interval nothing is late
+    let mut pacer = Pacer::new(Duration::ZERO);
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    pacer.wait().await;
+    assert_eq!(pacer.overrun_report(), None);
+}
+
+#[test]
+fn test_keep_slow_values() {
+    let mut old = VehicleState::new();
+    old.fuel = 80;
+    old.speed = 30;
+    let old_extra = ExtraValues::from([('v', 90), ('t', 1200)]);
+
+    let mut new = VehicleState::new();
+    new.fuel = 79;
+    new.speed = 31;
+    let mut new_extra = ExtraValues::from([('v', 91), ('t', 1300), ('w', 5)]);
+
+    keep_slow_values(&old, &mut new, &old_extra, &mut new_extra);
+
+    assert_eq!(new.fuel, 80);
+    assert_eq!(new.speed, 31);
+    assert_eq!(new_extra, ExtraValues::from([('v', 90), ('t', 1300)]));
+}
@@ -0,0 +1,194 @@
+use std::fs;
+
+use serialport::{FlowControl, Parity, SerialPortInfo, SerialPortType, UsbPortInfo};
+use the_bus_2_komsi::config::Config;
+use the_bus_2_komsi::serial::{port_listed, LineMode, PortSelector, UsbId};
+
+fn port_info(serial_number: Option<&str>) -> UsbPortInfo {
+    UsbPortInfo {
+        vid: 0x2341,
+        pid: 0x0043,
+        serial_number: serial_number.map(str::to_string),
+        manufacturer: Some("Arduino".to_string()),
+        product: Some("Uno".to_string()),
+    }
+}
+
+#[test]
+fn test_usb_id_parse() {
+    let id = UsbId::parse("2341:0043:SERIAL123").unwrap();
+    assert_eq!(id.vid, 0x2341);
+    assert_eq!(id.pid, 0x0043);
+    assert_eq!(id.serial_number.as_deref(), Some("SERIAL123"));
+    assert_eq!(id.to_string(), "2341:0043:SERIAL123");
+
+    let id = UsbId::parse(" 10c4:EA60 ").unwrap();
+    assert_eq!(id.pid, 0xea60);
+    assert_eq!(id.serial_number, None);
+    assert_eq!(PortSelector::Usb(id).to_string(), "usb 10c4:ea60");
+
+    assert!(UsbId::parse("2341").is_err());
+    assert!(UsbId::parse("COM3").is_err());
+    assert!(UsbId::parse("2341:xyz").is_err());
+}
+
+#[test]
+fn test_usb_id_matches() {
+    let id = UsbId::parse("2341:0043:serial123").unwrap();
+    assert!(id.matches(&port_info(Some("SERIAL123"))));
+    // Windows adds suffixes to the serial number
+    assert!(id.matches(&port_info(Some("SERIAL123A"))));
+    assert!(!id.matches(&port_info(Some("OTHER"))));
+    assert!(!id.matches(&port_info(None)));
+
+    let id = UsbId::parse("2341:0043").unwrap();
+    assert!(id.matches(&port_info(None)));
+    assert!(!UsbId::parse("2341:0042").unwrap().matches(&port_info(None)));
+}
+
+#[test]
+fn test_port_listed() {
+    let ports = vec![SerialPortInfo {
+        port_name: "COM8".to_string(),
+        port_type: SerialPortType::UsbPort(port_info(None)),
+    }];
+    // the example config has portname = com8, only Windows ignores the case
+    assert_eq!(port_listed(&ports, "com8"), cfg!(windows));
+    assert!(port_listed(&ports, "COM8"));
+    assert!(!port_listed(&ports, "com9"));
+    assert!(!port_listed(&[], "com8"));
+}
+
+/// `/dev/serial/by-id/...` links are not listed, the ports they point to are
+#[cfg(unix)]
+#[test]
+fn test_port_listed_link() {
+    let dir = std::env::temp_dir().join(format!("thebus2komsi_by_id_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let device = dir.join("ttyACM0");
+    let link = dir.join("usb-Arduino_Uno-if00");
+    fs::write(&device, "").unwrap();
+    let _ = fs::remove_file(&link);
+    std::os::unix::fs::symlink(&device, &link).unwrap();
+
+    let ports = vec![SerialPortInfo {
+        port_name: fs::canonicalize(&device).unwrap().to_string_lossy().into_owned(),
+        port_type: SerialPortType::Unknown,
+    }];
+    let listed = port_listed(&ports, link.to_str().unwrap());
+    let upper = port_listed(&ports, &ports[0].port_name.to_uppercase());
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(listed);
+    assert!(!upper);
+}
+
+/// A pseudo terminal stands in for the dashboard
+#[cfg(unix)]
+#[tokio::test]
+async fn test_serial_sink_task() {
+    use std::time::Duration;
+
+    use komsi::KomsiCommand;
+    use the_bus_2_komsi::sink::{KomsiSink, SerialOptions, SerialSink};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_serial::{SerialPort, SerialStream};
+    use tokio_util::sync::CancellationToken;
+
+    let (mut device, port) = SerialStream::pair().unwrap();
+    let portname = port.name().unwrap();
+    drop(port);
+
+    let shutdown = CancellationToken::new();
+    let mut sink = SerialSink::open(
+        PortSelector::Name(portname),
+        SerialOptions {
+            init: b"O1\n".to_vec(),
+            shutdown: shutdown.clone(),
+            ..Default::default()
+        },
+    );
+
+    // the task opens the port and sends the init frame, the bridge sends the whole state
+    let mut buffer = [0u8; 3];
+    device.read_exact(&mut buffer).await.unwrap();
+    assert_eq!(&buffer, b"O1\n");
+    assert!(sink.reconnected());
+
+    let mut tries = 0;
+    while let Err(e) = sink.send(b"A1\n") {
+        assert_eq!(e.kind(), std::io::ErrorKind::NotConnected);
+        tries += 1;
+        assert!(tries < 100, "port not opened");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(sink.connected());
+    device.read_exact(&mut buffer).await.unwrap();
+    assert_eq!(&buffer, b"A1\n");
+
+    // commands of the device arrive without polling the port
+    device.write_all(b"H1\n").await.unwrap();
+    let mut received = Vec::new();
+    for _ in 0..100 {
+        received.extend(sink.receive());
+        if !received.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(received, vec![KomsiCommand::FrontDoor(true)]);
+
+    // after the shutdown the last frame is still written before the port is closed
+    shutdown.cancel();
+    sink.send(b"A0\n").unwrap();
+    sink.close().unwrap();
+    tokio::time::timeout(Duration::from_secs(2), sink.closing().unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    device.read_exact(&mut buffer).await.unwrap();
+    assert_eq!(&buffer, b"A0\n");
+}
+
+#[test]
+fn test_port_sections() {
+    let path = std::env::temp_dir().join(format!("thebus2komsi_ports_{}.ini", std::process::id()));
+    fs::write(
+        &path,
+        "[default]\nportname = COM8\nbaudrate = 57600\n\
+         [port.lamps]\nusb = 2341:0043\nfilter = A-N\ndtr = off\n\
+         [port.gauges]\ndevice = COM3\nbaudrate = 9600\nparity = even\nflowcontrol = hardware\nrts = pulse\n\
+         [port.spare]\ndevice = COM4\nenabled = false\n\
+         [port.broken]\ndevice = COM5\nusb = 2341:0043\n",
+    )
+    .unwrap();
+
+    let config = Config::load(&path, false);
+    fs::remove_file(&path).unwrap();
+
+    let names: Vec<&str> = config.ports.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, vec!["portname", "gauges", "lamps", "spare"]);
+
+    let [portname, gauges, lamps, spare] = &config.ports[..] else {
+        unreachable!()
+    };
+    assert_eq!(portname.settings.baudrate, 57600);
+
+    assert_eq!(gauges.selector, PortSelector::Name("COM3".to_string()));
+    assert_eq!(gauges.settings.baudrate, 9600);
+    assert_eq!(gauges.settings.parity, Parity::Even);
+    assert_eq!(gauges.settings.flow_control, FlowControl::Hardware);
+    assert_eq!(gauges.settings.rts, LineMode::Pulse);
+    assert_eq!(gauges.settings.dtr, LineMode::Keep);
+    assert!(gauges.filter.is_all());
+
+    // the baudrate of [default] if the section has none
+    assert_eq!(lamps.settings.baudrate, 57600);
+    assert_eq!(lamps.settings.dtr, LineMode::Off);
+    assert!(matches!(lamps.selector, PortSelector::Usb(_)));
+    assert!(lamps.filter.allows('A'));
+    assert!(!lamps.filter.allows('y'));
+
+    assert!(!spare.enabled);
+    assert!(gauges.enabled);
+}
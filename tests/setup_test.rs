@@ -0,0 +1,73 @@
+use std::fs;
+
+use the_bus_2_komsi::config::Config;
+use the_bus_2_komsi::serial::{PortSelector, UsbId};
+use the_bus_2_komsi::setup::{identify_frame, setup_ini, SetupAnswers};
+
+fn read(ini: &str) -> Config {
+    let path = std::env::temp_dir().join(format!("thebus2komsi_setup_{}.ini", std::process::id()));
+    fs::write(&path, ini).unwrap();
+    let config = Config::read(&path, false);
+    fs::remove_file(&path).unwrap();
+    config
+}
+
+fn answers() -> SetupAnswers {
+    SetupAnswers {
+        port: PortSelector::Name("COM5".to_string()),
+        baudrate: 57600,
+        ip: "192.168.1.20".to_string(),
+    }
+}
+
+#[test]
+fn test_setup_ini() {
+    let example = fs::read_to_string("TheBus2Komsi.ini").unwrap();
+    let ini = setup_ini(&example, &answers());
+    // the comments of the example are kept
+    assert!(ini.contains("# TheBus2Komsi -l\n"));
+
+    // every answer replaced its line of the example
+    for line in ["portname = COM5", "baudrate = 57600", "ip = 192.168.1.20"] {
+        assert!(ini.lines().any(|l| l == line), "{} missing", line);
+    }
+    for line in ["portname = com8", "baudrate = 115200", "ip = 127.0.0.1"] {
+        assert!(!ini.lines().any(|l| l == line), "{} still there", line);
+    }
+
+    let config = read(&ini);
+    assert!(config.problems.is_empty(), "{:?}", config.problems);
+    assert_eq!(config.ip, "192.168.1.20");
+    assert_eq!(config.ports.len(), 1);
+    assert_eq!(config.ports[0].selector, PortSelector::Name("COM5".to_string()));
+    assert_eq!(config.ports[0].settings.baudrate, 57600);
+
+    let usb = UsbId::parse("2341:0043:ABC").unwrap();
+    let config = read(&setup_ini(
+        &example,
+        &SetupAnswers {
+            port: PortSelector::Usb(usb.clone()),
+            baudrate: 115200,
+            ip: "127.0.0.1".to_string(),
+        },
+    ));
+    assert_eq!(config.ports.len(), 1);
+    assert_eq!(config.ports[0].selector, PortSelector::Usb(usb));
+}
+
+#[test]
+fn test_setup_ini_adds_missing_lines() {
+    // an edited example without ip and baudrate
+    let example = "# my dashboard\n[default]\nportname = com8\n";
+    let config = read(&setup_ini(example, &answers()));
+    assert!(config.problems.is_empty(), "{:?}", config.problems);
+    assert_eq!(config.ip, "192.168.1.20");
+    assert_eq!(config.ports[0].selector, PortSelector::Name("COM5".to_string()));
+    assert_eq!(config.ports[0].settings.baudrate, 57600);
+}
+
+#[test]
+fn test_identify_frame() {
+    let frame = String::from_utf8(identify_frame()).unwrap();
+    assert_eq!(frame, "A1B1C1D3E1F1G1H1I1J1K1L1M1y50x100\n");
+}
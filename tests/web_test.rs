@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use the_bus_2_komsi::sink::KomsiSink;
+use the_bus_2_komsi::values::KomsiValues;
+use the_bus_2_komsi::web::WebSink;
+
+async fn http_get(address: std::net::SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[test]
+fn test_komsi_values() {
+    let mut values = KomsiValues::new();
+    let changes = values.apply_frame(b"A1B0y50r20260101094348\n");
+    assert_eq!(changes.len(), 4);
+
+    // only what really changed
+    let changes = values.apply_frame(b"A1y60\n");
+    assert_eq!(changes, vec![('y', "60".to_string())]);
+
+    assert_eq!(
+        values.to_json(),
+        json!({
+            "ignition": true,
+            "engine": false,
+            "datetime": "2026-01-01T09:43:48",
+            "speed": 60
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_web_sink() {
+    let mut sink = WebSink::bind("127.0.0.1:0", false).await.unwrap();
+    sink.send(b"O1\n").unwrap();
+    sink.send(b"A1y50\n").unwrap();
+
+    let response = http_get(sink.address(), "/state").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    let state: Value = serde_json::from_str(body).unwrap();
+    assert_eq!(state, json!({"simulator_type": 1, "ignition": true, "speed": 50}));
+
+    let response = http_get(sink.address(), "/nothing").await;
+    assert!(response.starts_with("HTTP/1.1 404"));
+
+    let url = format!("ws://{}/ws", sink.address());
+    let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+    let mut next = async || -> Value {
+        let message = timeout(Duration::from_secs(5), ws.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        serde_json::from_str(message.to_text().unwrap()).unwrap()
+    };
+
+    // the whole state first, then the changes
+    let message = next().await;
+    assert_eq!(message["type"], "state");
+    assert_eq!(message["state"]["speed"], 50);
+
+    sink.send(b"A1y60D1\n").unwrap();
+    let message = next().await;
+    assert_eq!(message["type"], "diff");
+    assert_eq!(message["changes"], json!({"speed": 60, "indicator": 1}));
+    assert_eq!(message["state"]["speed"], 60);
+    assert_eq!(message["state"]["ignition"], true);
+}
+
+#[tokio::test]
+async fn test_web_sink_idle_client() {
+    let sink = WebSink::bind("127.0.0.1:0", false).await.unwrap();
+
+    // a client which never sends a request is dropped
+    let mut idle = TcpStream::connect(sink.address()).await.unwrap();
+    let mut buf = [0u8; 16];
+    let n = timeout(Duration::from_secs(10), idle.read(&mut buf))
+        .await
+        .expect("the idle connection was not closed")
+        .unwrap_or(0);
+    assert_eq!(n, 0);
+}